@@ -0,0 +1,21 @@
+#![no_main]
+
+use sup_ai::{ExpressionParser, UsagePreferences};
+
+libfuzzer_sys::fuzz_target!(|data: (&[u8], usize)| {
+    let (expr, split) = data;
+    let split = split % (expr.len() + 1);
+
+    let mut whole = UsagePreferences::default();
+    let mut p = ExpressionParser::new(&mut whole);
+    p.feed(expr);
+    p.finish();
+
+    let mut pieces = UsagePreferences::default();
+    let mut p = ExpressionParser::new(&mut pieces);
+    p.feed(&expr[..split]);
+    p.feed(&expr[split..]);
+    p.finish();
+
+    assert_eq!(whole.to_string(), pieces.to_string());
+});
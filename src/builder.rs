@@ -0,0 +1,245 @@
+use alloc::{string::String, vec, vec::Vec};
+use core::{error::Error, fmt};
+
+use crate::{Carrier, ExpressionFormat, UsagePreference, Vocabulary};
+
+/// The reasons that an expression might not be built.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum BuildError {
+    /// The label is not in the vocabulary.
+    UnknownLabel(Vec<u8>),
+    /// The label is not valid UTF-8.
+    NotUtf8(Vec<u8>),
+    /// The label cannot be conveyed by the carrier.
+    Unsupported { label: Vec<u8>, carrier: Carrier },
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownLabel(label) => {
+                write!(f, "unknown label \"{}\"", label.escape_ascii())
+            }
+            Self::NotUtf8(label) => write!(f, "label \"{}\" is not UTF-8", label.escape_ascii()),
+            Self::Unsupported { label, carrier } => {
+                write!(
+                    f,
+                    "label \"{}\" cannot be used in {carrier}",
+                    label.escape_ascii()
+                )
+            }
+        }
+    }
+}
+
+impl Error for BuildError {}
+
+/// Produces a preference expression from a set of values.
+///
+/// Values are checked against a vocabulary and included
+/// in the order that the vocabulary lists labels.
+pub struct PreferenceExpressionBuilder<'a> {
+    vocabulary: &'a Vocabulary,
+    values: Vec<Option<UsagePreference>>,
+}
+
+impl<'a> PreferenceExpressionBuilder<'a> {
+    #[must_use]
+    pub fn new(vocabulary: &'a Vocabulary) -> Self {
+        Self {
+            vocabulary,
+            values: vec![None; vocabulary.len()],
+        }
+    }
+
+    /// Set the value for a label, replacing any value that was set before.
+    ///
+    /// # Errors
+    /// If the label is not in the vocabulary.
+    pub fn set(
+        &mut self,
+        usage: impl AsRef<[u8]>,
+        value: UsagePreference,
+    ) -> Result<(), BuildError> {
+        let usage = usage.as_ref();
+        let i = self
+            .vocabulary
+            .index_of(usage)
+            .ok_or_else(|| BuildError::UnknownLabel(usage.to_vec()))?;
+        self.values[i] = Some(value);
+        Ok(())
+    }
+
+    /// Produce an expression in the given format,
+    /// with every label checked against the carrier, if there is one.
+    fn build_inner(
+        &self,
+        format: ExpressionFormat,
+        carrier: Option<Carrier>,
+    ) -> Result<String, BuildError> {
+        let members = self
+            .values
+            .iter()
+            .enumerate()
+            .filter_map(|(i, v)| Some((&self.vocabulary.labels[i].name[..], (*v)?)))
+            .map(|(name, value)| Ok((check_label(name, carrier)?, value)))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(write_expression(format, members))
+    }
+
+    /// Produce an expression in the loose syntax from the draft.
+    ///
+    /// # Errors
+    /// If a label with a value is not valid UTF-8.
+    pub fn build(&self) -> Result<String, BuildError> {
+        self.build_inner(ExpressionFormat::Loose, None)
+    }
+
+    /// Produce an expression that can be conveyed by the given carrier.
+    ///
+    /// For `Carrier::HttpHeader`, this is a Structured Field Dictionary, per RFC 9651.
+    /// For `Carrier::RobotsTxt`, this is the value of a "Content-Usage" rule.
+    /// The result is empty if no values are set.
+    ///
+    /// # Errors
+    /// If a label with a value cannot be conveyed by the carrier.
+    pub fn build_for(&self, carrier: Carrier) -> Result<String, BuildError> {
+        self.build_inner(carrier.format(), Some(carrier))
+    }
+}
+
+/// Check that a label is UTF-8 and, if a carrier is given, that the carrier can convey it.
+pub(crate) fn check_label(label: &[u8], carrier: Option<Carrier>) -> Result<&str, BuildError> {
+    let Ok(s) = core::str::from_utf8(label) else {
+        return Err(BuildError::NotUtf8(label.to_vec()));
+    };
+    if let Some(carrier) = carrier.filter(|c| !c.accepts(label)) {
+        return Err(BuildError::Unsupported {
+            label: label.to_vec(),
+            carrier,
+        });
+    }
+    Ok(s)
+}
+
+/// Join labels that have passed `check_label()` and their values into an expression.
+///
+/// Dictionary keys and the "y" and "n" tokens need no escaping,
+/// so a dictionary is written the same way as the loose syntax,
+/// with only the separator differing.
+pub(crate) fn write_expression<'a>(
+    format: ExpressionFormat,
+    members: impl IntoIterator<Item = (&'a str, UsagePreference)>,
+) -> String {
+    let mut expr = String::new();
+    for (label, value) in members {
+        if !expr.is_empty() {
+            expr.push_str(format.separator());
+        }
+        expr.push_str(label);
+        expr.push('=');
+        expr.push(match value {
+            UsagePreference::Allowed => 'y',
+            UsagePreference::Denied => 'n',
+        });
+    }
+    expr
+}
+
+#[cfg(test)]
+mod test {
+    use alloc::{sync::Arc, vec};
+
+    use super::{BuildError, PreferenceExpressionBuilder};
+    use crate::{
+        Carrier, ExpressionFormat,
+        UsagePreference::{Allowed, Denied},
+        UsagePreferences, UsagePreferencesAssertions, Vocabulary, VocabularyError,
+    };
+
+    #[test]
+    fn build() {
+        let v = Arc::new(Vocabulary::aipref());
+        let mut b = PreferenceExpressionBuilder::new(&v);
+        assert_eq!(b.build().unwrap(), "");
+        assert_eq!(b.build_for(Carrier::HttpHeader).unwrap(), "");
+
+        b.set(UsagePreferences::SEARCH, Denied).unwrap();
+        b.set(UsagePreferences::TRAIN_AI, Allowed).unwrap();
+        b.set(UsagePreferences::ALL, Allowed).unwrap();
+        b.set(UsagePreferences::TRAIN_AI, Denied).unwrap();
+        assert_eq!(
+            b.set("tdm", Allowed),
+            Err(BuildError::UnknownLabel(b"tdm".to_vec()))
+        );
+
+        let loose = b.build().unwrap();
+        assert_eq!(loose, "all=y,train-ai=n,search=n");
+        assert_eq!(b.build_for(Carrier::RobotsTxt).unwrap(), loose);
+        let http = b.build_for(Carrier::HttpHeader).unwrap();
+        assert_eq!(http, "all=y, train-ai=n, search=n");
+
+        for (expr, format) in [
+            (loose, ExpressionFormat::Loose),
+            (http, ExpressionFormat::Dictionary),
+        ] {
+            let mut up = v.preferences();
+            assert_eq!(up.parse_with_diagnostics(&expr), []);
+            assert_eq!(up.to_expression(format), expr);
+            up.assert_allowed(UsagePreferences::ALL);
+            up.assert_denied(UsagePreferences::TRAIN_GENAI);
+            up.assert_allowed(UsagePreferences::AI_USE);
+            up.assert_denied(UsagePreferences::SEARCH);
+        }
+    }
+
+    #[test]
+    fn unsupported() {
+        let mut v = Vocabulary::default();
+        v.add("Upper");
+        v.add("a#b");
+        v.add([0xff]);
+        v.add("/x");
+        v.add("a b");
+        assert_eq!(v.try_add("", &[]), Err(VocabularyError::Empty));
+        let mut b = PreferenceExpressionBuilder::new(&v);
+        b.set("Upper", Denied).unwrap();
+        assert_eq!(b.build().unwrap(), "Upper=n");
+        assert_eq!(b.build_for(Carrier::RobotsTxt).unwrap(), "Upper=n");
+        assert_eq!(
+            b.build_for(Carrier::HttpHeader),
+            Err(BuildError::Unsupported {
+                label: b"Upper".to_vec(),
+                carrier: Carrier::HttpHeader
+            })
+        );
+
+        let mut b = PreferenceExpressionBuilder::new(&v);
+        b.set("a#b", Allowed).unwrap();
+        assert_eq!(b.build().unwrap(), "a#b=y");
+        assert_eq!(
+            b.build_for(Carrier::RobotsTxt),
+            Err(BuildError::Unsupported {
+                label: b"a#b".to_vec(),
+                carrier: Carrier::RobotsTxt
+            })
+        );
+
+        // These would be read as a path in robots.txt or not survive a registry.
+        for label in ["/x", "a b"] {
+            let mut b = PreferenceExpressionBuilder::new(&v);
+            b.set(label, Denied).unwrap();
+            assert_eq!(
+                b.build_for(Carrier::RobotsTxt),
+                Err(BuildError::Unsupported {
+                    label: label.as_bytes().to_vec(),
+                    carrier: Carrier::RobotsTxt
+                })
+            );
+        }
+
+        let mut b = PreferenceExpressionBuilder::new(&v);
+        b.set([0xff], Allowed).unwrap();
+        assert_eq!(b.build(), Err(BuildError::NotUtf8(vec![0xff])));
+    }
+}
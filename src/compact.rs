@@ -0,0 +1,220 @@
+use crate::{State, UsagePreference, UsagePreferences, Vocabulary};
+
+/// The values for a vocabulary of up to 32 labels, in 16 bytes.
+///
+/// Each label uses two bits for the value that was set,
+/// one that is set if the label is allowed and one that is set if it is denied,
+/// and two more bits for the outcome, which includes values that are inherited.
+/// The outcome is found using the hierarchy from the vocabulary when this is created,
+/// so evaluation and merging do not need the vocabulary.
+/// Labels are identified by their index in the vocabulary;
+/// see `Vocabulary::index_of()`.
+///
+/// Use `UsagePreferences::compact()` to create this.
+#[derive(Default, PartialEq, Eq, Hash, Clone, Copy, Debug)]
+pub struct CompactPreferences {
+    allowed: u32,
+    denied: u32,
+    resolved_allowed: u32,
+    resolved_denied: u32,
+}
+
+impl CompactPreferences {
+    /// The most labels that this can hold.
+    pub const MAX_LABELS: usize = 32;
+
+    /// Combine with another set of preferences that use the same vocabulary,
+    /// in the same way as `UsagePreferences::merge()`.
+    ///
+    /// Each value is combined with the outcome for the label in `other`,
+    /// with a denial from either taking precedence.
+    /// A label that has no value after that has no value in `other`
+    /// or in anything it inherits from there, so its outcome is unchanged.
+    pub fn merge(&mut self, other: Self) {
+        self.denied |= other.resolved_denied;
+        self.allowed = (self.allowed | other.resolved_allowed) & !self.denied;
+        let set = self.allowed | self.denied;
+        self.resolved_denied = self.denied | (self.resolved_denied & !set);
+        self.resolved_allowed = self.allowed | (self.resolved_allowed & !set);
+    }
+
+    /// Evaluate the usage preference for the label at index `i`
+    /// in the vocabulary that was used to create this.
+    #[must_use]
+    pub fn eval_index(&self, i: usize, dflt: UsagePreference) -> UsagePreference {
+        let Some(bit) = u32::try_from(i).ok().and_then(|i| 1_u32.checked_shl(i)) else {
+            return dflt;
+        };
+        if self.resolved_denied & bit != 0 {
+            UsagePreference::Denied
+        } else if self.resolved_allowed & bit != 0 {
+            UsagePreference::Allowed
+        } else {
+            dflt
+        }
+    }
+
+    /// Evaluate the usage preference against the given usage,
+    /// which is found in the vocabulary that was used to create this.
+    #[must_use]
+    pub fn eval(
+        &self,
+        vocabulary: &Vocabulary,
+        usage: impl AsRef<[u8]>,
+        dflt: UsagePreference,
+    ) -> UsagePreference {
+        vocabulary
+            .index_of(usage)
+            .map_or(dflt, |i| self.eval_index(i, dflt))
+    }
+}
+
+impl UsagePreferences {
+    /// Produce a compact form of these preferences.
+    ///
+    /// This returns `None` if the vocabulary has more than
+    /// `CompactPreferences::MAX_LABELS` labels.
+    /// Provenance is not retained.
+    #[must_use]
+    pub fn compact(&self) -> Option<CompactPreferences> {
+        if self.values.len() > CompactPreferences::MAX_LABELS {
+            return None;
+        }
+        let mut c = CompactPreferences::default();
+        for (i, value) in self.values.iter().enumerate() {
+            match value {
+                State::Unknown => {}
+                State::Yes => c.allowed |= 1 << i,
+                State::No => c.denied |= 1 << i,
+            }
+            match self.get_state(i) {
+                State::Unknown => {}
+                State::Yes => c.resolved_allowed |= 1 << i,
+                State::No => c.resolved_denied |= 1 << i,
+            }
+        }
+        Some(c)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use alloc::sync::Arc;
+    use core::mem::size_of;
+
+    use super::CompactPreferences;
+    use crate::{
+        UsagePreference::{Allowed, Denied},
+        UsagePreferences, Vocabulary,
+    };
+
+    #[test]
+    fn size() {
+        assert_eq!(size_of::<CompactPreferences>(), 16);
+    }
+
+    #[test]
+    fn eval() {
+        let mut up = UsagePreferences::default();
+        up.parse("all=y,train-ai=n");
+        let c = up.compact().unwrap();
+        let v = up.vocabulary();
+        assert_eq!(c.eval(v, UsagePreferences::ALL, Denied), Allowed);
+        assert_eq!(c.eval(v, UsagePreferences::TRAIN_GENAI, Allowed), Denied);
+        assert_eq!(c.eval(v, UsagePreferences::SEARCH, Denied), Allowed);
+        assert_eq!(c.eval(v, "unknown", Denied), Denied);
+        assert_eq!(c.eval_index(v.index_of("search").unwrap(), Denied), Allowed);
+        assert_eq!(c.eval_index(32, Allowed), Allowed);
+        assert_eq!(c.eval_index(usize::MAX, Denied), Denied);
+
+        let empty = UsagePreferences::default().compact().unwrap();
+        assert_eq!(empty, CompactPreferences::default());
+        assert_eq!(empty.eval(v, UsagePreferences::ALL, Denied), Denied);
+    }
+
+    #[test]
+    fn merge() {
+        let v = Arc::new(Vocabulary::aipref());
+        let mut up1 = v.preferences();
+        up1.parse("all=y,search=n");
+        let mut up2 = v.preferences();
+        up2.parse("train-ai=n,search=y,ai-use=y");
+        let mut c = up1.compact().unwrap();
+        c.merge(up2.compact().unwrap());
+        assert_eq!(c.eval(&v, UsagePreferences::ALL, Denied), Allowed);
+        assert_eq!(c.eval(&v, UsagePreferences::TRAIN_AI, Allowed), Denied);
+        assert_eq!(c.eval(&v, UsagePreferences::TRAIN_GENAI, Allowed), Denied);
+        assert_eq!(c.eval(&v, UsagePreferences::AI_USE, Denied), Allowed);
+        assert_eq!(c.eval(&v, UsagePreferences::SEARCH, Allowed), Denied);
+    }
+
+    /// Merging compact forms has the same outcome as merging the preferences,
+    /// including when the result is merged again.
+    #[test]
+    fn merge_matches() {
+        const EXPRS: &[&str] = &[
+            "",
+            "all=n",
+            "all=y",
+            "train-ai=y",
+            "train-ai=n,train-genai=y",
+            "all=n,search=y",
+            "ai-use=y,search=n",
+        ];
+        let v = Arc::new(Vocabulary::aipref());
+        let prefs = |expr| {
+            let mut up = UsagePreferences::new(Arc::clone(&v));
+            up.parse(expr);
+            up
+        };
+        for a in EXPRS {
+            for b in EXPRS {
+                for c in EXPRS {
+                    let mut merged = prefs(a);
+                    let mut compact = prefs(a).compact().unwrap();
+                    for other in [b, c] {
+                        merged.merge(&prefs(other));
+                        compact.merge(prefs(other).compact().unwrap());
+                        assert_eq!(Some(compact), merged.compact(), "{a} + {b} + {c}");
+                        for i in 0..v.len() {
+                            assert_eq!(
+                                compact.eval_index(i, Allowed),
+                                merged.eval(v.labels[i].name.as_slice(), Allowed),
+                                "{a} + {b} + {c}"
+                            );
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn multiple_parents() {
+        let mut v = Vocabulary::default();
+        v.add("a");
+        v.add("b");
+        v.add_with_parents("c", ["a", "b"]);
+        let v = Arc::new(v);
+        let mut up = v.preferences();
+        up.parse("a=y,b=n");
+        let c = up.compact().unwrap();
+        assert_eq!(c.eval(&v, "c", Allowed), Denied);
+        up.parse("c=y");
+        let c = up.compact().unwrap();
+        assert_eq!(c.eval(&v, "c", Denied), Allowed);
+    }
+
+    #[test]
+    fn too_many_labels() {
+        let mut up = UsagePreferences::blank();
+        for i in 0..CompactPreferences::MAX_LABELS {
+            up.add(alloc::format!("l{i}"));
+        }
+        up.parse("l31=n");
+        let c = up.compact().unwrap();
+        assert_eq!(c.eval(up.vocabulary(), "l31", Allowed), Denied);
+        up.add("one-more");
+        assert!(up.compact().is_none());
+    }
+}
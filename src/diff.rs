@@ -0,0 +1,158 @@
+use alloc::vec::Vec;
+
+use crate::{State, UsagePreference, UsagePreferences};
+
+/// Why the outcome for a label changed.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum ChangeKind {
+    /// The value for the label itself changed.
+    Direct,
+    /// The label has the same value, or no value,
+    /// but the value it inherits from a more general label changed.
+    Inherited,
+}
+
+/// A change to the outcome for a label.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Change {
+    pub label: Vec<u8>,
+    /// The outcome before, if there was one.
+    pub before: Option<UsagePreference>,
+    /// The outcome after, if there is one.
+    pub after: Option<UsagePreference>,
+    pub kind: ChangeKind,
+}
+
+impl Change {
+    /// Whether the usage is now denied when it was not before.
+    #[must_use]
+    pub fn became_denied(&self) -> bool {
+        self.after == Some(UsagePreference::Denied)
+    }
+}
+
+impl UsagePreferences {
+    /// The value and the outcome for the named label.
+    fn value_and_state(&self, usage: &[u8]) -> (State, State) {
+        self.index_of(usage)
+            .map_or((State::Unknown, State::Unknown), |i| {
+                (self.values[i], self.get_state(i))
+            })
+    }
+
+    /// Find the labels with an outcome that differs between these preferences,
+    /// which come before, and `other`, which come after.
+    ///
+    /// Labels are listed in the order that these preferences list them,
+    /// followed by any labels that only `other` knows.
+    #[must_use]
+    pub fn diff(&self, other: &Self) -> Vec<Change> {
+        let mine = (0..self.values.len()).map(|i| self.name(i));
+        let theirs = (0..other.values.len())
+            .map(|i| other.name(i))
+            .filter(|n| self.index_of(n).is_none());
+        mine.chain(theirs)
+            .filter_map(|label| {
+                let (value_before, before) = self.value_and_state(label);
+                let (value_after, after) = other.value_and_state(label);
+                (before != after).then(|| Change {
+                    label: label.to_vec(),
+                    before: UsagePreference::try_from(before).ok(),
+                    after: UsagePreference::try_from(after).ok(),
+                    kind: if value_before == value_after {
+                        ChangeKind::Inherited
+                    } else {
+                        ChangeKind::Direct
+                    },
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use alloc::{sync::Arc, vec::Vec};
+
+    use super::{Change, ChangeKind};
+    use crate::{
+        UsagePreference::{self, Allowed, Denied},
+        UsagePreferences, Vocabulary, prefs,
+    };
+
+    fn change(
+        label: &str,
+        before: Option<UsagePreference>,
+        after: Option<UsagePreference>,
+        kind: ChangeKind,
+    ) -> Change {
+        Change {
+            label: label.as_bytes().to_vec(),
+            before,
+            after,
+            kind,
+        }
+    }
+
+    #[test]
+    fn diff() {
+        let before = prefs("all=y,train-ai=y,search=y");
+        let after = prefs("all=n,train-ai=y,search=n");
+        assert_eq!(
+            before.diff(&after),
+            [
+                change(
+                    UsagePreferences::ALL,
+                    Some(Allowed),
+                    Some(Denied),
+                    ChangeKind::Direct
+                ),
+                change(
+                    UsagePreferences::AI_USE,
+                    Some(Allowed),
+                    Some(Denied),
+                    ChangeKind::Inherited
+                ),
+                change(
+                    UsagePreferences::SEARCH,
+                    Some(Allowed),
+                    Some(Denied),
+                    ChangeKind::Direct
+                ),
+            ]
+        );
+        let denied = before
+            .diff(&after)
+            .into_iter()
+            .filter(Change::became_denied)
+            .count();
+        assert_eq!(denied, 3);
+
+        assert_eq!(after.diff(&after), []);
+        // Different values with the same outcome are not a change.
+        assert_eq!(prefs("all=n").diff(&prefs("all=n,search=n")), []);
+    }
+
+    #[test]
+    fn diff_unset() {
+        let d = prefs("train-ai=n").diff(&prefs(""));
+        let labels = d.iter().map(|c| &c.label[..]).collect::<Vec<_>>();
+        assert_eq!(labels, [&b"train-ai"[..], b"train-genai"]);
+        assert_eq!(d[0].kind, ChangeKind::Direct);
+        assert_eq!(d[1].kind, ChangeKind::Inherited);
+        assert_eq!(d[1].after, None);
+        assert!(!d[1].became_denied());
+    }
+
+    #[test]
+    fn diff_vocabulary() {
+        let mut v = Vocabulary::aipref();
+        v.add_child("x", UsagePreferences::ALL);
+        let mut after = Arc::new(v).preferences();
+        after.parse("all=n");
+        assert_eq!(
+            prefs("").diff(&after).last(),
+            Some(&change("x", None, Some(Denied), ChangeKind::Inherited))
+        );
+    }
+}
@@ -0,0 +1,163 @@
+use alloc::vec::Vec;
+
+use crate::{UsagePreference, UsagePreferences};
+
+/// Where the outcome of an evaluation came from.
+#[derive(PartialEq, Eq, Clone, Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum DecisionSource {
+    /// The label that was evaluated had a value.
+    Label,
+    /// The label had no value, so the value of this more general label was used.
+    #[cfg_attr(
+        feature = "serde",
+        serde(serialize_with = "crate::serde::serialize_label")
+    )]
+    Ancestor(Vec<u8>),
+    /// No value was found, either for the label or any more general label,
+    /// or the label is not known, so the default was used.
+    Default,
+}
+
+/// A label that was examined during evaluation.
+#[derive(PartialEq, Eq, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct TraceStep {
+    #[cfg_attr(
+        feature = "serde",
+        serde(serialize_with = "crate::serde::serialize_label")
+    )]
+    pub label: Vec<u8>,
+    /// The value that was recorded for this label, if any.
+    pub value: Option<UsagePreference>,
+}
+
+/// The outcome of an evaluation, with an account of how it was reached.
+#[derive(PartialEq, Eq, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Explanation {
+    pub outcome: UsagePreference,
+    pub source: DecisionSource,
+    /// The labels that were examined, in the order they were visited.
+    pub trace: Vec<TraceStep>,
+}
+
+impl Explanation {
+    /// Whether the outcome is the default that was provided.
+    #[must_use]
+    pub fn default_used(&self) -> bool {
+        self.source == DecisionSource::Default
+    }
+}
+
+impl UsagePreferences {
+    /// Evaluate the usage preference against the given usage, as with `eval`,
+    /// and explain how the outcome was reached.
+    #[must_use]
+    pub fn eval_explained(&self, usage: impl AsRef<[u8]>, dflt: UsagePreference) -> Explanation {
+        let Some(i) = self.index_of(usage.as_ref()) else {
+            return Explanation {
+                outcome: dflt,
+                source: DecisionSource::Default,
+                trace: Vec::new(),
+            };
+        };
+        let mut trace = Vec::new();
+        let (state, source) = self.resolve(i, &mut |v| {
+            trace.push(TraceStep {
+                label: self.name(v).to_vec(),
+                value: UsagePreference::try_from(self.values[v]).ok(),
+            });
+        });
+        let (outcome, source) = match (UsagePreference::try_from(state), source) {
+            (Ok(outcome), Some(s)) if s == i => (outcome, DecisionSource::Label),
+            (Ok(outcome), Some(s)) => (outcome, DecisionSource::Ancestor(self.name(s).to_vec())),
+            _ => (dflt, DecisionSource::Default),
+        };
+        Explanation {
+            outcome,
+            source,
+            trace,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{DecisionSource, TraceStep};
+    use crate::{
+        UsagePreference::{self, Allowed, Denied},
+        UsagePreferences,
+    };
+
+    fn step(label: &str, value: Option<UsagePreference>) -> TraceStep {
+        TraceStep {
+            label: label.as_bytes().to_vec(),
+            value,
+        }
+    }
+
+    #[test]
+    fn label() {
+        let mut up = UsagePreferences::default();
+        up.parse("all=y,train-ai=n");
+        let e = up.eval_explained(UsagePreferences::TRAIN_AI, Allowed);
+        assert_eq!(e.outcome, Denied);
+        assert_eq!(e.source, DecisionSource::Label);
+        assert_eq!(e.trace, [step("train-ai", Some(Denied))]);
+    }
+
+    #[test]
+    fn ancestor() {
+        let mut up = UsagePreferences::default();
+        up.parse("all=n");
+        let e = up.eval_explained(UsagePreferences::TRAIN_GENAI, Allowed);
+        assert_eq!(e.outcome, Denied);
+        assert_eq!(e.source, DecisionSource::Ancestor(b"all".to_vec()));
+        assert_eq!(
+            e.trace,
+            [
+                step("train-genai", None),
+                step("train-ai", None),
+                step("all", Some(Denied)),
+            ]
+        );
+        assert!(!e.default_used());
+    }
+
+    #[test]
+    fn default() {
+        let up = UsagePreferences::default();
+        let e = up.eval_explained(UsagePreferences::SEARCH, Denied);
+        assert_eq!(e.outcome, Denied);
+        assert!(e.default_used());
+        assert_eq!(e.trace, [step("search", None), step("all", None)]);
+    }
+
+    #[test]
+    fn unknown_label() {
+        let mut up = UsagePreferences::default();
+        up.parse("all=n");
+        let e = up.eval_explained("tdm", Allowed);
+        assert_eq!(e.outcome, Allowed);
+        assert!(e.default_used());
+        assert!(e.trace.is_empty());
+    }
+
+    #[test]
+    fn multiple_parents() {
+        let mut up = UsagePreferences::blank();
+        up.add("a");
+        up.add("b");
+        up.add_with_parents("c", ["a", "b"]);
+        up.parse("a=y,b=n");
+        let e = up.eval_explained("c", Allowed);
+        assert_eq!(e.outcome, Denied);
+        assert_eq!(e.source, DecisionSource::Ancestor(b"b".to_vec()));
+        assert_eq!(e.trace.len(), 3);
+    }
+}
@@ -0,0 +1,193 @@
+use alloc::vec::Vec;
+
+use crate::{State, UsagePreferences};
+
+impl UsagePreferences {
+    /// The state of the named label, including any value inherited from a more general label.
+    /// Labels that are not known have no value.
+    fn state_of(&self, usage: &[u8]) -> State {
+        self.index_of(usage)
+            .map_or(State::Unknown, |i| self.get_state(i))
+    }
+
+    /// Compare the outcome for every label in either set of preferences.
+    fn all_labels(&self, other: &Self, mut f: impl FnMut(State, State) -> bool) -> bool {
+        (0..self.values.len()).all(|i| f(self.get_state(i), other.state_of(self.name(i))))
+            && (0..other.values.len())
+                .filter(|&i| self.index_of(other.name(i)).is_none())
+                .all(|i| f(State::Unknown, other.get_state(i)))
+    }
+
+    /// Combine two sets of preferences, with the more permissive value taking precedence.
+    ///
+    /// Denying a usage is stricter than expressing no preference,
+    /// which is stricter than allowing it.
+    /// For each label, the outcome is the less strict of the two:
+    /// if either allows the usage, the usage is allowed;
+    /// otherwise, if either has no preference, there is no preference;
+    /// the usage is only denied if both deny it.
+    /// The result is therefore no stricter than either.
+    /// Inherited values are considered,
+    /// so afterwards every label with an outcome has its own value.
+    /// Labels that are only known to one of the two have no value in the other,
+    /// and labels from `other` that this does not track are ignored.
+    pub fn join(&mut self, other: &Self) {
+        let before = (0..self.values.len())
+            .map(|i| self.get_state(i))
+            .collect::<Vec<_>>();
+        for (i, mut state) in before.into_iter().enumerate() {
+            let mine = state;
+            let theirs = other
+                .index_of(self.name(i))
+                .map_or((State::Unknown, None), |idx| {
+                    other.resolve(idx, &mut |_| {})
+                });
+            state.join(theirs.0);
+            self.values[i] = state;
+            if state != mine
+                && let Some(p) = theirs.1.and_then(|s| other.provenance.get(s))
+                && !p.is_empty()
+            {
+                self.provenance_mut(i).extend_from_slice(p);
+            }
+        }
+    }
+
+    /// Whether these preferences are at least as strict as `other` for every label.
+    ///
+    /// Inherited values are considered.
+    /// See `join()` for how strictness is ordered
+    /// and how labels that only one of the two knows are treated.
+    #[must_use]
+    pub fn is_at_least_as_strict_as(&self, other: &Self) -> bool {
+        self.all_labels(other, |a, b| a.strictness() >= b.strictness())
+    }
+}
+
+/// Preferences are equal if every label has the same outcome,
+/// even if they have different values.
+/// For example, "all=n" and "all=n,search=n" are equal
+/// when "search" is more specific than "all".
+///
+/// Labels are compared as for `join()`.
+/// Provenance and limits are not compared.
+/// Neither are values kept for labels that are not in the vocabulary,
+/// which have no outcome; see `set_preserve_unknown()`.
+/// Preferences that are equal can therefore produce different expressions.
+impl PartialEq for UsagePreferences {
+    fn eq(&self, other: &Self) -> bool {
+        self.all_labels(other, |a, b| a == b)
+    }
+}
+
+impl Eq for UsagePreferences {}
+
+#[cfg(test)]
+mod test {
+    use alloc::sync::Arc;
+
+    use crate::{
+        ExpressionFormat, UsagePreferences, UsagePreferencesAssertions, Vocabulary, prefs,
+    };
+
+    #[test]
+    fn join() {
+        let mut up = prefs("all=n,train-ai=y");
+        up.join(&prefs("search=y,train-genai=n,ai-use=n"));
+        up.assert_unset(UsagePreferences::ALL);
+        up.assert_allowed(UsagePreferences::TRAIN_AI);
+        up.assert_allowed(UsagePreferences::TRAIN_GENAI);
+        up.assert_denied(UsagePreferences::AI_USE);
+        up.assert_allowed(UsagePreferences::SEARCH);
+
+        // An inherited denial does not win over an allowance.
+        let mut up = prefs("all=y");
+        up.join(&prefs("all=n,search=y"));
+        up.assert_allowed(UsagePreferences::ALL);
+        up.assert_allowed(UsagePreferences::SEARCH);
+
+        let mut up = prefs("");
+        up.join(&prefs(""));
+        assert_eq!(up, prefs(""));
+        assert_eq!(up.to_expression(ExpressionFormat::Loose), "");
+
+        // Having no preference is less strict than a denial.
+        let mut up = prefs("");
+        up.join(&prefs("all=n"));
+        assert_eq!(up, prefs(""));
+    }
+
+    /// A join is no stricter than either input.
+    #[test]
+    fn join_upper_bound() {
+        const EXPRS: &[&str] = &[
+            "",
+            "all=n",
+            "all=y",
+            "train-ai=n",
+            "all=n,search=y",
+            "all=y,train-genai=n",
+        ];
+        for a in EXPRS {
+            for b in EXPRS {
+                let mut joined = prefs(a);
+                joined.join(&prefs(b));
+                assert!(prefs(a).is_at_least_as_strict_as(&joined), "{a} + {b}");
+                assert!(prefs(b).is_at_least_as_strict_as(&joined), "{a} + {b}");
+            }
+        }
+    }
+
+    #[test]
+    fn strictness() {
+        let strict = prefs("all=n");
+        let loose = prefs("all=y,search=n");
+        let none = prefs("");
+        assert!(strict.is_at_least_as_strict_as(&loose));
+        assert!(!loose.is_at_least_as_strict_as(&strict));
+        assert!(strict.is_at_least_as_strict_as(&none));
+        assert!(!none.is_at_least_as_strict_as(&strict));
+        assert!(none.is_at_least_as_strict_as(&prefs("search=y")));
+        assert!(!none.is_at_least_as_strict_as(&loose));
+        assert!(!loose.is_at_least_as_strict_as(&none));
+        assert!(loose.is_at_least_as_strict_as(&loose));
+
+        // The inherited value is what counts.
+        assert!(prefs("train-ai=n").is_at_least_as_strict_as(&prefs("train-genai=n")));
+        assert!(!prefs("train-genai=n").is_at_least_as_strict_as(&prefs("train-ai=n")));
+
+        // A republished dataset might loosen preferences.
+        let mut republished = strict.clone();
+        republished.join(&loose);
+        assert!(!republished.is_at_least_as_strict_as(&strict));
+    }
+
+    #[test]
+    fn equality() {
+        assert_eq!(prefs("all=n"), prefs("all=n,search=n,train-ai=n"));
+        assert_eq!(prefs("train-ai=y"), prefs("train-genai=y,train-ai=y"));
+        assert_ne!(prefs("all=n"), prefs("all=n,search=y"));
+        assert_ne!(prefs(""), prefs("train-genai=n"));
+        assert_eq!(prefs(""), UsagePreferences::blank());
+
+        // Labels that are not shared have no value in the other.
+        let mut other = Vocabulary::default();
+        other.add(UsagePreferences::SEARCH);
+        other.add("x");
+        let mut up = Arc::new(other).preferences();
+        up.parse("search=n");
+        assert_eq!(up, prefs("search=n"));
+        up.parse("x=y");
+        assert_ne!(up, prefs("search=n"));
+
+        // Preserved labels are not compared.
+        let mut up = UsagePreferences::default();
+        up.set_preserve_unknown(true);
+        up.parse("tdm=n");
+        assert_eq!(up, prefs(""));
+        assert_ne!(
+            up.to_expression(ExpressionFormat::Loose),
+            prefs("").to_expression(ExpressionFormat::Loose)
+        );
+    }
+}
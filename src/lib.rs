@@ -1,7 +1,40 @@
-use std::cmp::max;
+#![no_std]
 
+extern crate alloc;
+#[cfg(any(feature = "std", test))]
+extern crate std;
+
+use alloc::{string::String, sync::Arc, vec::Vec};
+use core::{fmt, ops::Range};
+
+mod builder;
+mod compact;
+mod diff;
+mod explain;
+mod lattice;
+mod limits;
+mod minimize;
+mod profile;
+mod provenance;
+mod push;
 #[cfg(feature = "robots")]
 pub mod robots;
+#[cfg(feature = "serde")]
+mod serde;
+mod translation;
+mod vocabulary;
+
+pub use builder::{BuildError, PreferenceExpressionBuilder};
+use builder::{check_label, write_expression};
+pub use compact::CompactPreferences;
+pub use diff::{Change, ChangeKind};
+pub use explain::{DecisionSource, Explanation, TraceStep};
+pub use limits::ParseLimits;
+pub use profile::UsageProfile;
+pub use provenance::{Provenance, Source, SourceKind};
+pub use push::ExpressionParser;
+pub use translation::Translation;
+pub use vocabulary::{Carrier, Registration, RegistryError, Status, Vocabulary, VocabularyError};
 
 #[derive(Default, PartialEq, Eq, Clone, Copy, Debug)]
 enum State {
@@ -24,9 +57,35 @@ impl State {
             (Self::Unknown, Self::Unknown) => Self::Unknown,
         };
     }
+
+    /// Produce a joined value from this and another value.
+    ///
+    /// This picks the less strict value, as ranked by `strictness()`:
+    /// if either is "Yes", pick "Yes".
+    /// Otherwise, if either is "Unknown", pick "Unknown".
+    fn join(&mut self, other: Self) {
+        if other.strictness() < self.strictness() {
+            *self = other;
+        }
+    }
+
+    /// How strict this value is:
+    /// "Yes" is the least strict, "No" is the most, and "Unknown" is in between.
+    fn strictness(self) -> u8 {
+        match self {
+            Self::Yes => 0,
+            Self::Unknown => 1,
+            Self::No => 2,
+        }
+    }
 }
 
 #[derive(PartialEq, Eq, Clone, Copy, Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(::serde::Serialize, ::serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
 pub enum UsagePreference {
     Allowed,
     Denied,
@@ -43,17 +102,146 @@ impl TryFrom<State> for UsagePreference {
     }
 }
 
-#[derive(Debug, Clone)]
-struct Item {
-    name: Vec<u8>,
-    parent: Option<usize>,
-    value: State,
+/// The syntax to use when turning preferences back into an expression.
+#[derive(Default, PartialEq, Eq, Clone, Copy, Debug)]
+pub enum ExpressionFormat {
+    /// The loose syntax from the draft, as used in robots.txt.
+    /// Members are separated by a comma, without whitespace.
+    #[default]
+    Loose,
+    /// A dictionary Structured Field, per RFC 9651, as used in HTTP.
+    /// Labels that are not valid dictionary keys are omitted.
+    Dictionary,
+}
+
+impl ExpressionFormat {
+    fn separator(self) -> &'static str {
+        match self {
+            Self::Loose => ",",
+            Self::Dictionary => ", ",
+        }
+    }
+
+    /// The carrier that limits the labels in this format, if any.
+    fn carrier(self) -> Option<Carrier> {
+        match self {
+            Self::Loose => None,
+            Self::Dictionary => Some(Carrier::HttpHeader),
+        }
+    }
+}
+
+/// The reason that part of a preference expression was ignored.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum DiagnosticKind {
+    /// The label is not one that is known.
+    UnknownLabel,
+    /// The value is something other than "y" or "n".
+    InvalidValue,
+    /// The member could not be split into a label and a value.
+    MalformedMember,
+    /// The member is a Structured Field Boolean, rather than a token.
+    BooleanMember,
+    /// The member has Structured Field parameters.
+    MemberWithParameters,
+    /// Processing stopped before the end of the input,
+    /// so nothing from this point onwards was used.
+    Truncated,
+}
+
+impl fmt::Display for DiagnosticKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::UnknownLabel => "unknown label",
+            Self::InvalidValue => "value is not \"y\" or \"n\"",
+            Self::MalformedMember => "malformed member",
+            Self::BooleanMember => "member has a boolean value",
+            Self::MemberWithParameters => "member has parameters",
+            Self::Truncated => "processing stopped",
+        })
+    }
+}
+
+/// An explanation for why some part of a preference expression was ignored.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Diagnostic {
+    pub kind: DiagnosticKind,
+    /// The range of bytes in the input that this applies to.
+    pub span: Range<usize>,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at {}..{}", self.kind, self.span.start, self.span.end)
+    }
+}
+
+/// Collects diagnostics and provenance, if there is somewhere to put them.
+struct Reporter<'a> {
+    diagnostics: Option<&'a mut Vec<Diagnostic>>,
+    source: Option<&'a Source>,
+}
+
+impl Reporter<'_> {
+    fn report(&mut self, kind: DiagnosticKind, span: Range<usize>) {
+        if let Some(d) = &mut self.diagnostics {
+            d.push(Diagnostic { kind, span });
+        }
+    }
+
+    /// Apply a value that was found in the given span of the input.
+    fn apply(
+        &self,
+        prefs: &mut UsagePreferences,
+        target: Target<'_>,
+        value: State,
+        span: Range<usize>,
+    ) {
+        let i = match target {
+            Target::Label(i) => i,
+            Target::Unknown(name) => {
+                prefs.add_unknown(name, value);
+                return;
+            }
+        };
+        prefs.values[i].merge(value);
+        if let (Some(source), Ok(value)) = (self.source, UsagePreference::try_from(value)) {
+            prefs.provenance_mut(i).push(Provenance {
+                source: source.clone(),
+                value,
+                span,
+            });
+        }
+    }
 }
 
+/// What the value in a member applies to.
+#[derive(Clone, Copy, Debug)]
+enum Target<'a> {
+    /// The label at this index in the vocabulary.
+    Label(usize),
+    /// A label that is not in the vocabulary, which is being preserved.
+    Unknown(&'a [u8]),
+}
+
+/// The preferences from one or more expressions.
+///
+/// This holds a value for each label in a `Vocabulary`,
+/// which can be shared with other sets of preferences.
+/// The first change to the labels, through `add()` and similar,
+/// makes a private copy of the vocabulary.
 #[derive(Debug, Clone)]
 pub struct UsagePreferences {
-    items: Vec<Item>,
-    max_len: usize,
+    vocabulary: Arc<Vocabulary>,
+    values: Vec<State>,
+    /// Where values came from, for each label.
+    /// This is empty until a value with a source is added.
+    provenance: Vec<Vec<Provenance>>,
+    limits: ParseLimits,
+    /// Whether to keep values for labels that are not in the vocabulary.
+    preserve_unknown: bool,
+    /// Values for labels that are not in the vocabulary, in the order they were first seen.
+    unknown: Vec<(Vec<u8>, State)>,
 }
 
 impl UsagePreferences {
@@ -90,32 +278,60 @@ impl UsagePreferences {
     /// Note: Use the `Default` implementation to get the standard set of usages.
     #[must_use]
     pub fn blank() -> Self {
+        Self::new(Arc::default())
+    }
+
+    /// Create a set of usage preferences for the labels in the given vocabulary,
+    /// none of which have a value.
+    #[must_use]
+    pub fn new(vocabulary: Arc<Vocabulary>) -> Self {
         Self {
-            items: Vec::new(),
-            max_len: 0,
+            values: alloc::vec![State::Unknown; vocabulary.len()],
+            vocabulary,
+            provenance: Vec::new(),
+            limits: ParseLimits::NONE,
+            preserve_unknown: false,
+            unknown: Vec::new(),
         }
     }
 
-    /// Common logic for the addition of a usage.
-    fn add_inner(&mut self, usage: impl AsRef<[u8]>, parent: Option<usize>) {
-        let name = usage.as_ref();
-        assert!(!name.contains(&b','), "usage name cannot contain a comma");
-        assert!(!name.contains(&b'='), "usage name cannot contain equals");
-        assert!(
-            !self.items.iter().any(|it| it.name == name),
-            "duplicate usage added"
-        );
-        self.max_len = max(self.max_len, name.len());
-        self.items.push(Item {
-            name: name.to_vec(),
-            parent,
-            value: State::Unknown,
-        });
+    /// The vocabulary that this uses.
+    #[must_use]
+    pub fn vocabulary(&self) -> &Arc<Vocabulary> {
+        &self.vocabulary
+    }
+
+    /// Get a private copy of the vocabulary to change.
+    /// Then, ensure that there is a value for every label.
+    fn update_vocabulary<T>(&mut self, f: impl FnOnce(&mut Vocabulary) -> T) -> T {
+        let result = f(Arc::make_mut(&mut self.vocabulary));
+        let n = self.vocabulary.len();
+        self.values.resize(n, State::Unknown);
+        if !self.provenance.is_empty() {
+            self.provenance.resize(n, Vec::new());
+        }
+        result
+    }
+
+    /// The provenance for label `i`, which can be modified.
+    fn provenance_mut(&mut self, i: usize) -> &mut Vec<Provenance> {
+        if self.provenance.is_empty() {
+            self.provenance.resize(self.values.len(), Vec::new());
+        }
+        &mut self.provenance[i]
+    }
+
+    /// The name of label `i`.
+    fn name(&self, i: usize) -> &[u8] {
+        &self.vocabulary.labels[i].name
     }
 
     /// Add a usage that this object will track.
+    ///
+    /// # Panics
+    /// This panics if the usage is empty, already present, or contains a comma or equals sign.
     pub fn add(&mut self, usage: impl AsRef<[u8]>) {
-        self.add_inner(usage, None);
+        self.update_vocabulary(|v| v.add(usage));
     }
 
     /// Add a usage that this object will track.
@@ -125,34 +341,128 @@ impl UsagePreferences {
     /// # Panics
     /// This panics if the identified parent cannot be found.
     pub fn add_child(&mut self, usage: impl AsRef<[u8]>, parent: impl AsRef<[u8]>) {
-        let parent = parent.as_ref();
-        let p = self
-            .items
-            .iter()
-            .position(|it| it.name == parent)
-            .expect("parent not found");
-        self.add_inner(usage, Some(p));
+        self.add_with_parents(usage, [parent]);
+    }
+
+    /// Add a usage that this object will track,
+    /// which is more specific than all of the identified parent types.
+    /// If there is no preference expressed for this usage,
+    /// the preference for each parent is used, with any "n" taking precedence.
+    ///
+    /// # Panics
+    /// This panics if any of the identified parents cannot be found.
+    pub fn add_with_parents(
+        &mut self,
+        usage: impl AsRef<[u8]>,
+        parents: impl IntoIterator<Item = impl AsRef<[u8]>>,
+    ) {
+        self.update_vocabulary(|v| v.add_with_parents(usage, parents));
+    }
+
+    /// Add a usage, as with `add()`, but return an error rather than panicking.
+    /// The usage must also be usable with each of the identified carriers.
+    ///
+    /// # Errors
+    /// If the usage is empty, already present, or contains a character that cannot be used.
+    pub fn try_add(
+        &mut self,
+        usage: impl AsRef<[u8]>,
+        carriers: &[Carrier],
+    ) -> Result<(), VocabularyError> {
+        self.try_add_with_parents(usage, [] as [&[u8]; 0], carriers)
+    }
+
+    /// Add a usage, as with `add_child()`, but return an error rather than panicking.
+    /// The usage must also be usable with each of the identified carriers.
+    ///
+    /// # Errors
+    /// If the usage cannot be added or the parent cannot be found.
+    pub fn try_add_child(
+        &mut self,
+        usage: impl AsRef<[u8]>,
+        parent: impl AsRef<[u8]>,
+        carriers: &[Carrier],
+    ) -> Result<(), VocabularyError> {
+        self.try_add_with_parents(usage, [parent], carriers)
+    }
+
+    /// Add a usage, as with `add_with_parents()`, but return an error rather than panicking.
+    /// The usage must also be usable with each of the identified carriers.
+    ///
+    /// # Errors
+    /// If the usage cannot be added or any parent cannot be found.
+    pub fn try_add_with_parents(
+        &mut self,
+        usage: impl AsRef<[u8]>,
+        parents: impl IntoIterator<Item = impl AsRef<[u8]>>,
+        carriers: &[Carrier],
+    ) -> Result<(), VocabularyError> {
+        // Check first, so that a shared vocabulary is only copied if it changes.
+        let usage = usage.as_ref();
+        let parents = self.vocabulary.check(usage, parents, carriers)?;
+        self.update_vocabulary(|v| v.insert(usage, parents));
+        Ok(())
     }
 
     /// Determine the state of a given thing,
     /// including the cascade from higher-level items in the hierarchy.
-    fn get_state(&self, mut i: usize) -> State {
-        loop {
-            if self.items[i].value != State::Unknown {
-                return self.items[i].value;
+    ///
+    /// Where an item has multiple parents, the state from each is found
+    /// and those are merged, so that "No" from any parent wins.
+    fn get_state(&self, i: usize) -> State {
+        self.resolve(i, &mut |_| {}).0
+    }
+
+    /// The logic for `get_state`, which also identifies the item that provided the state.
+    /// `visit` is called for each item that is examined, once.
+    fn resolve(&self, i: usize, visit: &mut impl FnMut(usize)) -> (State, Option<usize>) {
+        self.resolve_cached(i, visit, &mut Vec::new())
+    }
+
+    /// The logic for `resolve`, which remembers the outcome for items in `cache`.
+    /// Items can only be reached more than once when an item has multiple parents,
+    /// so the cache is only populated once such an item is found.
+    /// Without this, a vocabulary where many items have multiple parents
+    /// could take exponential time.
+    fn resolve_cached(
+        &self,
+        i: usize,
+        visit: &mut impl FnMut(usize),
+        cache: &mut Vec<Option<(State, Option<usize>)>>,
+    ) -> (State, Option<usize>) {
+        if let Some(Some(result)) = cache.get(i) {
+            return *result;
+        }
+        visit(i);
+        if self.values[i] != State::Unknown {
+            return (self.values[i], Some(i));
+        }
+        let parents = &self.vocabulary.labels[i].parents;
+        if parents.len() > 1 && cache.is_empty() {
+            cache.resize(i, None);
+        }
+        let mut result = (State::Unknown, None);
+        for &p in parents {
+            debug_assert!(p < i, "avoid any potential infinite loop");
+            let (state, source) = self.resolve_cached(p, visit, cache);
+            if let Some(c) = cache.get_mut(p) {
+                *c = Some((state, source));
+            }
+            let mut merged = result.0;
+            merged.merge(state);
+            if merged != result.0 {
+                result = (merged, source);
+            }
+            if result.0 == State::No {
+                break;
             }
-            i = if let Some(p) = self.items[i].parent {
-                debug_assert!(p < i, "avoid any potential infinite loop");
-                p
-            } else {
-                return State::Unknown;
-            };
         }
+        result
     }
 
     /// Find the index of the given item.
     fn index_of(&self, usage: &[u8]) -> Option<usize> {
-        self.items.iter().position(|it| it.name == usage)
+        self.vocabulary.index_of(usage)
     }
 
     /// Evaluate the usage preference against the given usage.
@@ -164,35 +474,209 @@ impl UsagePreferences {
     }
 
     /// Combine two sets of preferences.
+    ///
+    /// Values that `other` preserved for labels it does not know are used
+    /// if this knows those labels.
+    /// See `set_preserve_unknown()`.
     pub fn merge(&mut self, other: &Self) {
-        for item in &mut self.items {
-            if let Some(idx) = other.index_of(&item.name) {
-                item.value.merge(other.get_state(idx))
+        let same = Arc::ptr_eq(&self.vocabulary, &other.vocabulary);
+        for i in 0..self.values.len() {
+            let idx = if same {
+                Some(i)
+            } else {
+                other.index_of(self.name(i))
+            };
+            if let Some(idx) = idx {
+                let (state, source) = other.resolve(idx, &mut |_| {});
+                self.values[i].merge(state);
+                if let Some(p) = source.and_then(|s| other.provenance.get(s))
+                    && !p.is_empty()
+                {
+                    self.provenance_mut(i).extend_from_slice(p);
+                }
+            }
+        }
+        self.merge_unknown(other, same);
+    }
+
+    /// Take the values that `other` preserved for labels that this vocabulary knows.
+    /// If this preserves unknown labels, also keep the values from `other`
+    /// for labels that are not in the vocabulary.
+    /// These are kept as they were expressed, without any inherited value,
+    /// so that a recipient that knows the label can apply its own hierarchy.
+    fn merge_unknown(&mut self, other: &Self, same: bool) {
+        for (name, value) in &other.unknown {
+            match self.index_of(name) {
+                Some(i) => self.values[i].merge(*value),
+                None => self.add_unknown(name, *value),
+            }
+        }
+        if same {
+            return;
+        }
+        for (idx, value) in other.values.iter().enumerate() {
+            if self.index_of(other.name(idx)).is_none() {
+                self.add_unknown(other.name(idx), *value);
             }
         }
     }
 
+    /// Produce a preference expression that captures the values that this object holds.
+    ///
+    /// Only labels with a value are included, in the order that they were added,
+    /// followed by any labels that are not in the vocabulary,
+    /// if `set_preserve_unknown()` is used.
+    /// Labels that are not valid UTF-8 or cannot be represented in the chosen format
+    /// are omitted.
+    #[must_use]
+    pub fn to_expression(&self, format: ExpressionFormat) -> String {
+        let known = self.values.iter().enumerate().filter_map(|(i, value)| {
+            let value = match value {
+                State::Unknown => return None,
+                State::Yes => UsagePreference::Allowed,
+                State::No => UsagePreference::Denied,
+            };
+            Some((self.name(i), value))
+        });
+        let members = known
+            .chain(self.unknown_labels())
+            .filter_map(|(label, value)| Some((check_label(label, format.carrier()).ok()?, value)));
+        write_expression(format, members)
+    }
+
     /// Parse the provided input.
     ///
     /// This adds the rules in the provided string to those that this object already holds.
-    #[cfg(feature = "sfv")]
     pub fn parse(&mut self, expr: impl AsRef<[u8]>) {
-        let parser = ::sfv::Parser::new(&expr);
-        let mut visitor = crate::sfv::PreferenceVisitor { dict: self };
-        let _ignore_err = parser.parse_dictionary_with_visitor(&mut visitor);
+        _ = self.parse_inner(
+            expr.as_ref(),
+            &mut Reporter {
+                diagnostics: None,
+                source: None,
+            },
+        );
     }
 
-    /// Parse the provided input.
+    /// Parse the provided input, as with `parse()`,
+    /// collecting diagnostics for any part of the input that was ignored.
+    pub fn parse_with_diagnostics(&mut self, expr: impl AsRef<[u8]>) -> Vec<Diagnostic> {
+        let mut diagnostics = Vec::new();
+        _ = self.parse_inner(
+            expr.as_ref(),
+            &mut Reporter {
+                diagnostics: Some(&mut diagnostics),
+                source: None,
+            },
+        );
+        diagnostics
+    }
+
+    /// Set limits on how much of each expression is processed.
+    pub fn set_limits(&mut self, limits: ParseLimits) {
+        self.limits = limits;
+    }
+
+    /// The limits on how much of each expression is processed.
+    #[must_use]
+    pub fn limits(&self) -> ParseLimits {
+        self.limits
+    }
+
+    /// Choose whether to keep values for labels that are not in the vocabulary.
     ///
-    /// This adds the rules in the provided string to those that this object already holds.
+    /// By default, these are ignored, as the draft requires.
+    /// When they are kept, they have no effect on evaluation,
+    /// but they are included in any expression that is produced
+    /// and they are retained by `merge()`.
+    /// This suits an intermediary that passes preferences on to others,
+    /// who might know more labels.
+    pub fn set_preserve_unknown(&mut self, preserve: bool) {
+        self.preserve_unknown = preserve;
+    }
+
+    /// Whether values for labels that are not in the vocabulary are kept.
+    #[must_use]
+    pub fn preserves_unknown(&self) -> bool {
+        self.preserve_unknown
+    }
+
+    /// The values for labels that are not in the vocabulary,
+    /// in the order that each was first seen.
+    /// This is always empty unless `set_preserve_unknown()` is used.
+    pub fn unknown_labels(&self) -> impl Iterator<Item = (&[u8], UsagePreference)> {
+        self.unknown
+            .iter()
+            .filter_map(|(l, v)| Some((&l[..], UsagePreference::try_from(*v).ok()?)))
+    }
+
+    /// Keep a value for a label that is not in the vocabulary.
+    fn add_unknown(&mut self, name: &[u8], value: State) {
+        if value == State::Unknown || !self.preserve_unknown {
+            return;
+        }
+        if let Some((_, v)) = self.unknown.iter_mut().find(|(l, _)| l == name) {
+            v.merge(value);
+        } else {
+            self.unknown.push((name.to_vec(), value));
+        }
+    }
+
+    /// What a member with the given label applies to, if anything.
+    fn target<'a>(&self, name: &'a [u8]) -> Option<Target<'a>> {
+        self.index_of(name)
+            .map(Target::Label)
+            .or_else(|| self.preserve_unknown.then_some(Target::Unknown(name)))
+    }
+
+    /// Parse, subject to limits.
+    /// Returns true if processing stopped before the end of the input.
+    fn parse_inner(&mut self, expr: &[u8], report: &mut Reporter<'_>) -> bool {
+        let end = self.limits.expression_end(expr);
+        self.parse_members(&expr[..end], report);
+        if end < expr.len() {
+            report.report(DiagnosticKind::Truncated, end..expr.len());
+            true
+        } else {
+            false
+        }
+    }
+
+    #[cfg(feature = "sfv")]
+    fn parse_members(&mut self, expr: &[u8], report: &mut Reporter<'_>) {
+        let parser = ::sfv::Parser::new(expr);
+        let mut visitor = crate::sfv::PreferenceVisitor {
+            dict: self,
+            input: expr,
+            report,
+            resume: 0,
+        };
+        if parser.parse_dictionary_with_visitor(&mut visitor).is_err() {
+            let resume = visitor.resume;
+            visitor
+                .report
+                .report(DiagnosticKind::Truncated, resume..expr.len());
+        }
+    }
+
     #[cfg(not(feature = "sfv"))]
-    pub fn parse(&mut self, expr: impl AsRef<[u8]>) {
-        crate::manual::parse(self, expr);
+    fn parse_members(&mut self, expr: &[u8], report: &mut Reporter<'_>) {
+        // Limits are applied by the caller.
+        let mut machine = crate::push::Machine::new(ParseLimits::NONE);
+        machine.feed(self, report, expr);
+        machine.finish(self, report);
+    }
+}
+
+impl fmt::Display for UsagePreferences {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_expression(ExpressionFormat::Loose))
     }
 }
 
 #[cfg(feature = "sfv")]
 mod sfv {
+    use core::ops::Range;
+
     use sfv::{
         BareItemFromInput, Error as SfvError, KeyRef,
         visitor::{
@@ -201,13 +685,57 @@ mod sfv {
         },
     };
 
-    use super::{State, UsagePreferences};
+    use super::{DiagnosticKind, Reporter, State, Target, UsagePreferences};
 
-    pub struct PreferenceVisitor<'a> {
+    /// Find the end of the structured field construct that starts at `i`.
+    /// That ends at the first character that `stop` selects,
+    /// skipping over strings and inner lists.
+    /// Any trailing whitespace is excluded.
+    fn scan(input: &[u8], i: usize, stop: impl Fn(u8) -> bool) -> usize {
+        let end = find(input, i, stop);
+        end - input[..end]
+            .iter()
+            .rev()
+            .take_while(|c| c.is_ascii_whitespace())
+            .count()
+    }
+
+    /// Find the first character at or after `i` that `stop` selects,
+    /// skipping over strings and inner lists,
+    /// or the end of the input if there is none.
+    pub(crate) fn find(input: &[u8], mut i: usize, stop: impl Fn(u8) -> bool) -> usize {
+        let mut depth = 0_usize;
+        let mut quoted = false;
+        while let Some(&c) = input.get(i) {
+            if quoted {
+                match c {
+                    b'\\' => i += 1,
+                    b'"' => quoted = false,
+                    _ => {}
+                }
+            } else {
+                match c {
+                    b'"' => quoted = true,
+                    b'(' => depth += 1,
+                    b')' => depth = depth.saturating_sub(1),
+                    _ if depth == 0 && stop(c) => break,
+                    _ => {}
+                }
+            }
+            i += 1;
+        }
+        i.min(input.len())
+    }
+
+    pub struct PreferenceVisitor<'a, 'r> {
         pub dict: &'a mut UsagePreferences,
+        pub input: &'a [u8],
+        pub report: &'a mut Reporter<'r>,
+        /// The end of the last member that was completely processed.
+        pub resume: usize,
     }
 
-    impl<'a> DictionaryVisitor<'a> for PreferenceVisitor<'_> {
+    impl<'a> DictionaryVisitor<'a> for PreferenceVisitor<'_, '_> {
         type Error = SfvError;
 
         fn entry<'dv, 'ev>(
@@ -217,139 +745,131 @@ mod sfv {
         where
             'dv: 'ev,
         {
+            // The key borrows from the input, so its position can be recovered.
+            let start = key.as_str().as_ptr().addr() - self.input.as_ptr().addr();
+            let key = start..start + key.as_str().len();
+            let member_end = scan(self.input, key.end, |c| c == b',');
+
             // A linear search is good enough for a small vocabulary.
-            let item = self.dict.items.iter_mut().find_map(|p| {
-                if p.name == key.as_str().as_bytes() {
-                    Some(&mut p.value)
-                } else {
-                    None
-                }
-            });
-            Ok(item.map(|item| UsageVisitor { item }))
+            let item = self.dict.target(&self.input[key.clone()]);
+            if !matches!(item, Some(Target::Label(_))) {
+                self.report
+                    .report(DiagnosticKind::UnknownLabel, key.clone());
+            }
+            Ok(UsageVisitor {
+                dict: &mut *self.dict,
+                item,
+                input: self.input,
+                report: &mut *self.report,
+                resume: &mut self.resume,
+                member: key.start..member_end,
+                key_end: key.end,
+            })
         }
     }
 
-    struct UsageVisitor<'a> {
-        item: &'a mut State,
+    struct UsageVisitor<'a, 'r> {
+        dict: &'a mut UsagePreferences,
+        item: Option<Target<'a>>,
+        input: &'a [u8],
+        report: &'a mut Reporter<'r>,
+        resume: &'a mut usize,
+        member: Range<usize>,
+        key_end: usize,
     }
 
-    impl<'a> ItemVisitor<'a> for UsageVisitor<'_> {
+    impl<'a> ItemVisitor<'a> for UsageVisitor<'_, '_> {
         type Error = SfvError;
 
         fn bare_item<'pv>(
             self,
             bare_item: BareItemFromInput<'a>,
         ) -> Result<impl ParameterVisitor<'pv>, Self::Error> {
-            if let Some(v) = bare_item.as_token() {
-                match v.as_str() {
-                    "y" => self.item.merge(State::Yes),
-                    "n" => self.item.merge(State::No),
-                    _ => {}
+            let mut value = State::Unknown;
+            if self.item.is_some() {
+                if bare_item.as_boolean().is_some() {
+                    self.report.report(
+                        DiagnosticKind::BooleanMember,
+                        self.member.start..self.key_end,
+                    );
+                } else {
+                    match bare_item.as_token().map(|v| v.as_str()) {
+                        Some("y") => value = State::Yes,
+                        Some("n") => value = State::No,
+                        _ => {
+                            let start = self.key_end + 1;
+                            let end = scan(self.input, start, |c| c == b',' || c == b';');
+                            self.report.report(DiagnosticKind::InvalidValue, start..end);
+                        }
+                    }
                 }
             }
-            Ok(Ignored)
+            Ok(ValueVisitor {
+                dict: self.dict,
+                item: self.item,
+                value,
+                parameters: false,
+                report: self.report,
+                resume: self.resume,
+                member: self.member,
+            })
         }
     }
 
-    impl EntryVisitor<'_> for UsageVisitor<'_> {
+    impl EntryVisitor<'_> for UsageVisitor<'_, '_> {
         fn inner_list<'ilv>(self) -> Result<impl InnerListVisitor<'ilv>, Self::Error> {
+            if self.item.is_some() {
+                let start = self.key_end + 1;
+                let end = scan(self.input, start, |c| c == b',' || c == b';');
+                self.report.report(DiagnosticKind::InvalidValue, start..end);
+            }
             Ok(Ignored) // do nothing
         }
     }
-}
-
-#[cfg(not(feature = "sfv"))]
-mod manual {
-    use std::iter::Peekable;
-
-    use super::{Item, State, UsagePreferences};
 
-    /// A simple wrapper that makes handling input sequences easier.
-    trait Input {
-        fn peek(&mut self) -> Option<u8>;
-        fn next(&mut self) -> Option<u8>;
-        fn next_if(&mut self, f: impl FnOnce(u8) -> bool) -> Option<u8>;
-        fn skip_until(&mut self, f: impl Fn(u8) -> bool);
-        fn skip_ws(&mut self) {
-            self.skip_until(|c| !c.is_ascii_whitespace());
-        }
+    /// Holds a value until it is known that the member has no parameters.
+    struct ValueVisitor<'a, 'r> {
+        dict: &'a mut UsagePreferences,
+        item: Option<Target<'a>>,
+        value: State,
+        parameters: bool,
+        report: &'a mut Reporter<'r>,
+        resume: &'a mut usize,
+        member: Range<usize>,
     }
 
-    impl<'a, T> Input for Peekable<T>
-    where
-        T: Iterator<Item = &'a u8> + 'a,
-    {
-        fn peek(&mut self) -> Option<u8> {
-            Peekable::peek(self).copied().copied()
-        }
-
-        fn next(&mut self) -> Option<u8> {
-            Iterator::next(self).copied()
-        }
-
-        fn next_if(&mut self, f: impl FnOnce(u8) -> bool) -> Option<u8> {
-            Peekable::next_if(self, |&&c| f(c)).copied()
-        }
-
-        fn skip_until(&mut self, f: impl Fn(u8) -> bool) {
-            while Input::next_if(self, |c| !f(c)).is_some() {}
-        }
-    }
-
-    fn parse_name(items: &[Item], r: &mut impl Input, max_len: usize) -> Option<usize> {
-        r.skip_ws();
-        let mut n = Vec::with_capacity(max_len);
-        for _ in 0..max_len {
-            if let Some(c) = r.next_if(|c| c != b'=' && c != b',') {
-                n.push(c);
-            } else {
-                break;
-            }
-        }
-        r.skip_ws();
-        if r.next_if(|c| c == b'=').is_some() {
-            let usage = n.trim_ascii_end();
-            if let Some(pos) = items.iter().position(|it| it.name == usage) {
-                return Some(pos);
-            }
-        }
-        None
-    }
+    impl ParameterVisitor<'_> for ValueVisitor<'_, '_> {
+        type Error = SfvError;
 
-    fn parse_value(r: &mut impl Input) -> State {
-        r.skip_ws();
-        let v = match r.next() {
-            Some(b'y') => State::Yes,
-            Some(b'n') => State::No,
-            _ => State::Unknown,
-        };
-        r.skip_ws();
-        if matches!(r.peek(), None | Some(b',')) {
-            v
-        } else {
-            State::Unknown
+        fn parameter(
+            &mut self,
+            _key: &KeyRef,
+            _value: BareItemFromInput<'_>,
+        ) -> Result<(), Self::Error> {
+            self.parameters = true;
+            Ok(())
         }
-    }
 
-    pub fn parse(prefs: &mut UsagePreferences, expr: impl AsRef<[u8]>) {
-        let mut r = expr.as_ref().iter().peekable();
-        while r.peek().is_some() {
-            if let Some(i) = parse_name(&prefs.items, &mut r, prefs.max_len) {
-                let v = parse_value(&mut r);
-                prefs.items[i].value.merge(v);
+        fn finish(self) -> Result<(), Self::Error> {
+            if let Some(item) = self.item {
+                if self.parameters {
+                    self.report
+                        .report(DiagnosticKind::MemberWithParameters, self.member.clone());
+                } else {
+                    self.report
+                        .apply(self.dict, item, self.value, self.member.clone());
+                }
             }
-            r.skip_until(|c| c == b',');
-            _ = Iterator::next(&mut r); // Discard any ','.
+            *self.resume = self.member.end;
+            Ok(())
         }
     }
 }
 
-impl Default for UsagePreferences {
-    fn default() -> Self {
-        let mut v = Self {
-            items: Vec::with_capacity(4),
-            max_len: 0,
-        };
+impl UsagePreferences {
+    /// The vocabulary used by `default()`.
+    fn default_vocabulary() -> Vocabulary {
+        let mut v = Vocabulary::default();
         v.add(Self::ALL);
         v.add_child(Self::TRAIN_AI, Self::ALL);
         v.add_child(Self::TRAIN_GENAI, Self::TRAIN_AI);
@@ -359,6 +879,23 @@ impl Default for UsagePreferences {
     }
 }
 
+/// With the "std" feature, every default instance shares one vocabulary.
+/// Otherwise, each has its own.
+impl Default for UsagePreferences {
+    #[cfg(feature = "std")]
+    fn default() -> Self {
+        static VOCABULARY: std::sync::OnceLock<Arc<Vocabulary>> = std::sync::OnceLock::new();
+        Self::new(Arc::clone(
+            VOCABULARY.get_or_init(|| Arc::new(Self::default_vocabulary())),
+        ))
+    }
+
+    #[cfg(not(feature = "std"))]
+    fn default() -> Self {
+        Self::new(Arc::new(Self::default_vocabulary()))
+    }
+}
+
 #[cfg(test)]
 trait UsagePreferencesAssertions {
     fn assert_unset(&self, usage: &str);
@@ -394,9 +931,22 @@ impl UsagePreferencesAssertions for UsagePreferences {
     }
 }
 
+/// Parse an expression with the default vocabulary.
+#[cfg(test)]
+fn prefs(expr: &str) -> UsagePreferences {
+    let mut up = UsagePreferences::default();
+    up.parse(expr);
+    up
+}
+
 #[cfg(test)]
 mod test {
-    use crate::{UsagePreferences, UsagePreferencesAssertions};
+    use alloc::{format, string::ToString, sync::Arc, vec::Vec};
+
+    use crate::{
+        Carrier, Diagnostic, DiagnosticKind, ExpressionFormat, UsagePreference, UsagePreferences,
+        UsagePreferencesAssertions, Vocabulary, VocabularyError,
+    };
 
     const ALL: &str = UsagePreferences::ALL;
     const TRAIN_GENAI: &str = UsagePreferences::TRAIN_GENAI;
@@ -408,15 +958,15 @@ mod test {
     #[test]
     fn make_blank() {
         let up = UsagePreferences::blank();
-        assert_eq!(up.items.len(), 0);
-        assert_eq!(up.max_len, 0);
+        assert_eq!(up.values.len(), 0);
+        assert_eq!(up.vocabulary.max_len, 0);
     }
 
     #[test]
     fn make_default() {
         let up = UsagePreferences::default();
-        assert_eq!(up.items.len(), 5);
-        assert_eq!(up.max_len, 11);
+        assert_eq!(up.values.len(), 5);
+        assert_eq!(up.vocabulary.max_len, 11);
     }
 
     #[test]
@@ -447,6 +997,81 @@ mod test {
         up.add_child("this", "no");
     }
 
+    #[test]
+    #[cfg(feature = "std")]
+    fn shared_default() {
+        let up = UsagePreferences::default();
+        assert!(Arc::ptr_eq(
+            &up.vocabulary,
+            &UsagePreferences::default().vocabulary
+        ));
+    }
+
+    #[test]
+    fn try_add() {
+        let mut up = UsagePreferences::default();
+        let shared = Arc::clone(&up.vocabulary);
+        for (label, err) in [
+            ("all", VocabularyError::Duplicate),
+            ("a,b", VocabularyError::Comma),
+            ("a=b", VocabularyError::Equals),
+            (
+                "a#b",
+                VocabularyError::UnsupportedCharacter {
+                    carrier: Carrier::RobotsTxt,
+                    position: 1,
+                },
+            ),
+            (
+                "a\nb",
+                VocabularyError::UnsupportedCharacter {
+                    carrier: Carrier::RobotsTxt,
+                    position: 1,
+                },
+            ),
+            (
+                "ok ",
+                VocabularyError::UnsupportedCharacter {
+                    carrier: Carrier::RobotsTxt,
+                    position: 2,
+                },
+            ),
+            (
+                "/x",
+                VocabularyError::UnsupportedCharacter {
+                    carrier: Carrier::RobotsTxt,
+                    position: 0,
+                },
+            ),
+            ("", VocabularyError::Empty),
+            (
+                "Upper",
+                VocabularyError::UnsupportedCharacter {
+                    carrier: Carrier::HttpHeader,
+                    position: 0,
+                },
+            ),
+        ] {
+            let e = up.try_add(label, &[Carrier::RobotsTxt, Carrier::HttpHeader]);
+            assert_eq!(e, Err(err), "{label:?}");
+        }
+        assert_eq!(
+            up.try_add_child("x", "none", &[]),
+            Err(VocabularyError::UnknownParent)
+        );
+        assert_eq!(up.vocabulary().len(), 5);
+        // Nothing was copied for a failed addition.
+        assert!(Arc::ptr_eq(&shared, &up.vocabulary));
+
+        up.try_add("a b#", &[]).unwrap();
+        assert!(!Arc::ptr_eq(&shared, &up.vocabulary));
+        up.try_add("*x.y-z_0", &[Carrier::HttpHeader]).unwrap();
+        up.try_add_child("c", ALL, &[Carrier::RobotsTxt, Carrier::HttpHeader])
+            .unwrap();
+        up.parse("all=n");
+        up.assert_denied("c");
+    }
+
     #[test]
     fn allow_tdm() {
         let mut up = UsagePreferences::default();
@@ -581,6 +1206,27 @@ mod test {
         up1.assert_unset(SEARCH);
     }
 
+    #[test]
+    fn shared_vocabulary() {
+        let v = Arc::new(Vocabulary::aipref());
+        let mut up1 = UsagePreferences::new(Arc::clone(&v));
+        up1.parse("all=n");
+        let mut up2 = up1.clone();
+        assert!(Arc::ptr_eq(up2.vocabulary(), &v));
+
+        // Adding a label makes a copy of the vocabulary.
+        up2.add_child("x", ALL);
+        assert!(!Arc::ptr_eq(up2.vocabulary(), &v));
+        assert_eq!(v.len(), 5);
+        up2.assert_denied("x");
+        up2.parse("x=y");
+        up2.assert_allowed("x");
+        up1.assert_unset("x");
+
+        up1.merge(&up2);
+        up1.assert_denied(ALL);
+    }
+
     #[test]
     fn merge_unrelated() {
         let mut up1 = UsagePreferences::default();
@@ -597,4 +1243,256 @@ mod test {
         up1.assert_unset(SEARCH);
         up1.assert_unset("a");
     }
+
+    #[test]
+    fn preserve_unknown() {
+        let mut up = UsagePreferences::default();
+        assert!(!up.preserves_unknown());
+        up.parse("tdm=n");
+        assert_eq!(up.unknown_labels().count(), 0);
+
+        up.set_preserve_unknown(true);
+        let diagnostics = up.parse_with_diagnostics("tdm=n,search=y,x=y;p,tdm=y");
+        assert!(
+            diagnostics
+                .iter()
+                .any(|d| d.kind == DiagnosticKind::UnknownLabel)
+        );
+        // Unknown labels have no effect on evaluation.
+        up.assert_unset(ALL);
+        up.assert_allowed(SEARCH);
+        // Parameters disqualify a member; a denial wins.
+        assert_eq!(
+            up.unknown_labels().collect::<Vec<_>>(),
+            [(&b"tdm"[..], UsagePreference::Denied)]
+        );
+        assert_eq!(up.to_string(), "search=y,tdm=n");
+        assert_eq!(
+            up.to_expression(ExpressionFormat::Dictionary),
+            "search=y, tdm=n"
+        );
+    }
+
+    #[test]
+    fn merge_preserved() {
+        let mut up1 = UsagePreferences::default();
+        up1.set_preserve_unknown(true);
+        up1.parse("all=y,tdm=y,z=y");
+        let mut up2 = UsagePreferences::default();
+        up2.set_preserve_unknown(true);
+        up2.parse("tdm=n,y=y");
+        up1.merge(&up2);
+        assert_eq!(up1.to_string(), "all=y,tdm=n,z=y,y=y");
+
+        // Labels that only the other vocabulary knows are preserved.
+        let mut v = Vocabulary::aipref();
+        v.add_child("z", ALL);
+        let v = Arc::new(v);
+        let mut up3 = v.preferences();
+        up3.parse("all=n,z=n");
+        up1.merge(&up3);
+        up1.assert_denied(ALL);
+        assert!(up1.to_string().ends_with(",search=n,tdm=n,z=n,y=y"));
+
+        // Those labels are applied if this vocabulary knows them.
+        let mut up4 = v.preferences();
+        up4.merge(&up1);
+        up4.assert_denied("z");
+
+        // Without preservation, nothing is kept.
+        let mut up5 = UsagePreferences::default();
+        up5.merge(&up1);
+        assert_eq!(up5.unknown_labels().count(), 0);
+    }
+
+    #[test]
+    fn to_expression_empty() {
+        let up = UsagePreferences::default();
+        assert_eq!(up.to_expression(ExpressionFormat::Loose), "");
+        assert_eq!(up.to_expression(ExpressionFormat::Dictionary), "");
+    }
+
+    #[test]
+    fn to_expression_canonical() {
+        let mut up = UsagePreferences::default();
+        up.parse("search=n,train-genai=y,all=y,x=y,train-ai=y,train-ai=n");
+        assert_eq!(
+            up.to_expression(ExpressionFormat::Loose),
+            "all=y,train-ai=n,train-genai=y,search=n"
+        );
+        assert_eq!(
+            up.to_expression(ExpressionFormat::Dictionary),
+            "all=y, train-ai=n, train-genai=y, search=n"
+        );
+        assert_eq!(up.to_string(), up.to_expression(ExpressionFormat::Loose));
+    }
+
+    #[test]
+    fn to_expression_round_trip() {
+        let mut up = UsagePreferences::default();
+        up.parse("ai-use=n,all=y");
+        for format in [ExpressionFormat::Loose, ExpressionFormat::Dictionary] {
+            let mut copy = UsagePreferences::default();
+            copy.parse(up.to_expression(format));
+            assert_eq!(copy.to_string(), up.to_string());
+        }
+    }
+
+    #[test]
+    fn to_expression_invalid_key() {
+        let mut up = UsagePreferences::blank();
+        up.add("Upper");
+        up.add("sp ace");
+        up.add("ok");
+        up.values.fill(super::State::Yes);
+        assert_eq!(
+            up.to_expression(ExpressionFormat::Loose),
+            "Upper=y,sp ace=y,ok=y"
+        );
+        assert_eq!(up.to_expression(ExpressionFormat::Dictionary), "ok=y");
+    }
+
+    fn diagnostic(kind: DiagnosticKind, span: core::ops::Range<usize>) -> Diagnostic {
+        Diagnostic { kind, span }
+    }
+
+    #[test]
+    fn diagnostics_none() {
+        let mut up = UsagePreferences::default();
+        let d = up.parse_with_diagnostics("all=y, search=n");
+        assert_eq!(d, []);
+        up.assert_allowed(ALL);
+        up.assert_denied(SEARCH);
+    }
+
+    #[test]
+    fn diagnostics_unknown_and_invalid() {
+        let mut up = UsagePreferences::default();
+        let d = up.parse_with_diagnostics("tdm=y, all=no, search=n");
+        assert_eq!(
+            d,
+            [
+                diagnostic(DiagnosticKind::UnknownLabel, 0..3),
+                diagnostic(DiagnosticKind::InvalidValue, 11..13),
+            ]
+        );
+        up.assert_unset(ALL);
+        up.assert_denied(SEARCH);
+    }
+
+    #[test]
+    #[cfg(feature = "sfv")]
+    fn diagnostics_sfv() {
+        let mut up = UsagePreferences::default();
+        let d = up.parse_with_diagnostics(r#"all, search=n;x=1, ai-use="n", train-ai=?0"#);
+        assert_eq!(
+            d,
+            [
+                diagnostic(DiagnosticKind::BooleanMember, 0..3),
+                diagnostic(DiagnosticKind::MemberWithParameters, 5..17),
+                diagnostic(DiagnosticKind::InvalidValue, 26..29),
+                diagnostic(DiagnosticKind::BooleanMember, 31..39),
+            ]
+        );
+        for usage in EVERYTHING {
+            up.assert_unset(usage);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "sfv")]
+    fn diagnostics_sfv_truncated() {
+        let mut up = UsagePreferences::default();
+        let d = up.parse_with_diagnostics("all=y, search=n,, train-ai=n");
+        assert_eq!(d, [diagnostic(DiagnosticKind::Truncated, 15..28)]);
+        up.assert_allowed(ALL);
+        up.assert_denied(SEARCH);
+        up.assert_allowed(TRAIN_AI);
+    }
+
+    #[test]
+    #[cfg(not(feature = "sfv"))]
+    fn diagnostics_manual() {
+        let mut up = UsagePreferences::default();
+        let d = up.parse_with_diagnostics(" all , =y,, search = n ,train-ai=y n");
+        assert_eq!(
+            d,
+            [
+                diagnostic(DiagnosticKind::MalformedMember, 1..4),
+                diagnostic(DiagnosticKind::MalformedMember, 7..7),
+                diagnostic(DiagnosticKind::InvalidValue, 33..36),
+            ]
+        );
+        up.assert_unset(ALL);
+        up.assert_denied(SEARCH);
+        up.assert_unset(TRAIN_AI);
+    }
+
+    /// A domain where "d" is more specific than both "b" and "c".
+    fn diamond() -> UsagePreferences {
+        let mut up = UsagePreferences::blank();
+        up.add("a");
+        up.add_child("b", "a");
+        up.add("c");
+        up.add_with_parents("d", ["b", "c"]);
+        up
+    }
+
+    #[test]
+    fn multiple_parents_one_set() {
+        let mut up = diamond();
+        up.parse("c=y");
+        up.assert_unset("a");
+        up.assert_unset("b");
+        up.assert_allowed("c");
+        up.assert_allowed("d");
+    }
+
+    #[test]
+    fn multiple_parents_deny_wins() {
+        let mut up = diamond();
+        up.parse("b=y,c=n");
+        up.assert_denied("d");
+        let mut up = diamond();
+        up.parse("a=n,c=y");
+        up.assert_denied("b");
+        up.assert_denied("d");
+    }
+
+    #[test]
+    fn multiple_parents_specific() {
+        let mut up = diamond();
+        up.parse("a=n,c=n,d=y");
+        up.assert_allowed("d");
+    }
+
+    /// Each label narrows both labels from the level above.
+    /// Resolving a label examines each ancestor once, rather than once for each path.
+    #[test]
+    fn multiple_parents_ladder() {
+        const DEPTH: usize = 100;
+        let mut up = UsagePreferences::blank();
+        up.add("a0");
+        up.add("b0");
+        for i in 1..DEPTH {
+            let parents = [format!("a{}", i - 1), format!("b{}", i - 1)];
+            up.add_with_parents(format!("a{i}"), &parents);
+            up.add_with_parents(format!("b{i}"), &parents);
+        }
+        let last = format!("a{}", DEPTH - 1);
+        up.assert_unset(&last);
+        let mut visited = 0;
+        _ = up.resolve(up.index_of(last.as_bytes()).unwrap(), &mut |_| visited += 1);
+        assert_eq!(visited, DEPTH * 2 - 1);
+
+        up.parse("b0=n");
+        up.assert_denied(&last);
+    }
+
+    #[test]
+    #[should_panic(expected = "parent not found")]
+    fn add_no_second_parent() {
+        let mut up = UsagePreferences::default();
+        up.add_with_parents("this", [ALL, "no"]);
+    }
 }
@@ -0,0 +1,202 @@
+/// Limits on how much input is processed.
+///
+/// The default has no limits.
+/// Processing stops once a limit is reached; when that happens,
+/// `UsagePreferences::parse_with_diagnostics` reports `DiagnosticKind::Truncated`
+/// and `Robots::is_truncated` returns true.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct ParseLimits {
+    /// The number of bytes of a preference expression to process.
+    /// Processing stops after the last directive that ends within this many bytes,
+    /// so that a directive is either processed in full or not at all.
+    /// A directive that does not fit is not processed,
+    /// even if it is the first.
+    ///
+    /// Directives are separated by commas.
+    /// With the "sfv" feature, `UsagePreferences::parse()` reads a dictionary,
+    /// so a comma in a string does not separate directives there.
+    /// `ExpressionParser` always uses the loose syntax, where every comma does.
+    pub expression_len: usize,
+    /// The number of directives in a preference expression to process.
+    /// Directives are separated as for `expression_len`.
+    pub directives: usize,
+    /// The number of bytes of a robots.txt file to read.
+    /// Any line that extends past this point is ignored, and no more input is read.
+    pub robots_size: usize,
+    /// The length of a line in robots.txt, not including the line ending.
+    /// Longer lines are ignored.
+    pub robots_line_len: usize,
+}
+
+impl ParseLimits {
+    /// No limits.
+    pub const NONE: Self = Self {
+        expression_len: usize::MAX,
+        directives: usize::MAX,
+        robots_size: usize::MAX,
+        robots_line_len: usize::MAX,
+    };
+
+    /// Limits that suit processing of untrusted input.
+    ///
+    /// Expressions stop at 1000 characters, as the draft permits.
+    /// The robots.txt limit is the 500 KiB that RFC 9309 requires crawlers to process.
+    pub const RECOMMENDED: Self = Self {
+        expression_len: 1000,
+        directives: 100,
+        robots_size: 500 * 1024,
+        robots_line_len: 8 * 1024,
+    };
+
+    /// Find where processing of an expression stops.
+    pub(crate) fn expression_end(&self, expr: &[u8]) -> usize {
+        let commas = || separators(expr);
+        let by_len = match self.expression_len {
+            0 => 0,
+            n if expr.len() <= n => expr.len(),
+            n => commas().take_while(|&i| i <= n).last().unwrap_or(0),
+        };
+        let by_count = match self.directives {
+            0 => 0,
+            n => commas().nth(n - 1).unwrap_or(expr.len()),
+        };
+        by_len.min(by_count)
+    }
+}
+
+/// The positions of the commas that separate the members of an expression,
+/// skipping any in strings, as the dictionary parser does.
+#[cfg(feature = "sfv")]
+fn separators(expr: &[u8]) -> impl Iterator<Item = usize> {
+    let mut i = 0;
+    core::iter::from_fn(move || {
+        let comma = crate::sfv::find(expr, i, |c| c == b',');
+        i = comma + 1;
+        (comma < expr.len()).then_some(comma)
+    })
+}
+
+/// The positions of the commas that separate the members of an expression.
+#[cfg(not(feature = "sfv"))]
+fn separators(expr: &[u8]) -> impl Iterator<Item = usize> {
+    expr.iter()
+        .enumerate()
+        .filter_map(|(i, &c)| (c == b',').then_some(i))
+}
+
+impl Default for ParseLimits {
+    fn default() -> Self {
+        Self::NONE
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ParseLimits;
+    use crate::{DiagnosticKind, ExpressionParser, UsagePreferences, UsagePreferencesAssertions};
+
+    fn limits(expression_len: usize, directives: usize) -> ParseLimits {
+        ParseLimits {
+            expression_len,
+            directives,
+            ..ParseLimits::NONE
+        }
+    }
+
+    #[test]
+    fn expression_end() {
+        const EXPR: &[u8] = b"all=y,search=n,ai-use=n";
+        assert_eq!(ParseLimits::NONE.expression_end(EXPR), EXPR.len());
+        assert_eq!(limits(23, usize::MAX).expression_end(EXPR), EXPR.len());
+        assert_eq!(limits(5, usize::MAX).expression_end(EXPR), 5);
+        assert_eq!(limits(6, usize::MAX).expression_end(EXPR), 5);
+        assert_eq!(limits(7, usize::MAX).expression_end(EXPR), 5);
+        assert_eq!(limits(4, usize::MAX).expression_end(EXPR), 0);
+        assert_eq!(limits(0, usize::MAX).expression_end(EXPR), 0);
+        assert_eq!(limits(14, usize::MAX).expression_end(EXPR), 14);
+        assert_eq!(limits(22, usize::MAX).expression_end(EXPR), 14);
+        assert_eq!(limits(usize::MAX, 0).expression_end(EXPR), 0);
+        assert_eq!(limits(usize::MAX, 1).expression_end(EXPR), 5);
+        assert_eq!(limits(usize::MAX, 3).expression_end(EXPR), EXPR.len());
+        assert_eq!(limits(6, 1).expression_end(EXPR), 5);
+    }
+
+    /// A dictionary member is not split at a comma in a string.
+    #[test]
+    #[cfg(feature = "sfv")]
+    fn expression_end_string() {
+        const EXPR: &[u8] = b"a=\"x,\\\",y\", train-ai=n,search=n";
+        assert_eq!(limits(usize::MAX, 1).expression_end(EXPR), 10);
+        assert_eq!(limits(9, usize::MAX).expression_end(EXPR), 0);
+        assert_eq!(limits(22, usize::MAX).expression_end(EXPR), 22);
+
+        let mut up = UsagePreferences::default();
+        up.set_limits(limits(usize::MAX, 2));
+        let d = up.parse_with_diagnostics(EXPR);
+        assert_eq!(
+            d.iter()
+                .filter(|d| d.kind == DiagnosticKind::Truncated)
+                .count(),
+            1
+        );
+        up.assert_denied(UsagePreferences::TRAIN_AI);
+        up.assert_unset(UsagePreferences::SEARCH);
+    }
+
+    #[test]
+    fn truncated() {
+        let mut up = UsagePreferences::default();
+        up.set_limits(limits(7, usize::MAX));
+        let d = up.parse_with_diagnostics("all=y,search=n");
+        assert_eq!(d.len(), 1);
+        assert_eq!(d[0].kind, DiagnosticKind::Truncated);
+        assert_eq!(d[0].span, 5..14);
+        up.assert_allowed(UsagePreferences::ALL);
+        up.assert_allowed(UsagePreferences::SEARCH);
+    }
+
+    #[test]
+    fn long_member() {
+        // A single member that is longer than the limit is not processed.
+        let expr = alloc::format!("all=n{}", " ".repeat(2000));
+        let mut up = UsagePreferences::default();
+        up.set_limits(ParseLimits::RECOMMENDED);
+        let d = up.parse_with_diagnostics(&expr);
+        assert_eq!(d.len(), 1);
+        assert_eq!(d[0].kind, DiagnosticKind::Truncated);
+        assert_eq!(d[0].span, 0..expr.len());
+        up.assert_unset(UsagePreferences::ALL);
+
+        let mut up = UsagePreferences::default();
+        up.set_limits(ParseLimits::RECOMMENDED);
+        let mut p = ExpressionParser::new(&mut up);
+        p.feed(&expr);
+        p.finish();
+        up.assert_unset(UsagePreferences::ALL);
+    }
+
+    #[test]
+    fn draft_limit() {
+        let mut expr = "x=y,".repeat(250);
+        expr.push_str("all=n");
+        let mut up = UsagePreferences::default();
+        up.set_limits(limits(1000, usize::MAX));
+        up.parse(&expr);
+        up.assert_unset(UsagePreferences::ALL);
+
+        expr.truncate(996);
+        expr.push_str("all=n");
+        let mut up = UsagePreferences::default();
+        up.set_limits(limits(1000, usize::MAX));
+        up.parse(&expr);
+        up.assert_unset(UsagePreferences::ALL);
+
+        // This is exactly 1000 characters.
+        expr.truncate(992);
+        expr.push_str("   all=n");
+        let mut up = UsagePreferences::default();
+        up.set_limits(limits(1000, usize::MAX));
+        up.parse(&expr);
+        up.assert_denied(UsagePreferences::ALL);
+    }
+}
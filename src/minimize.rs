@@ -0,0 +1,101 @@
+use crate::{State, UsagePreferences};
+
+impl UsagePreferences {
+    /// Remove values that are the same as the value that would be inherited.
+    ///
+    /// This uses the hierarchy in the vocabulary to find the values that are redundant.
+    /// For example, "all=n,train-ai=n,search=y" becomes "all=n,search=y".
+    /// The outcome for every label is unchanged, so an expression produced afterwards
+    /// is shorter but means the same thing.
+    ///
+    /// Removing a redundant value does not change the outcome for any label,
+    /// so this removes every value that is redundant.
+    /// Provenance and any preserved labels that are not in the vocabulary are retained.
+    pub fn minimize(&mut self) {
+        for i in 0..self.values.len() {
+            let value = self.values[i];
+            if value == State::Unknown {
+                continue;
+            }
+            self.values[i] = State::Unknown;
+            if self.get_state(i) != value {
+                self.values[i] = value;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use alloc::{string::ToString, sync::Arc, vec::Vec};
+
+    use crate::{State, UsagePreferences, Vocabulary};
+
+    fn minimized(expr: &str) -> UsagePreferences {
+        let mut up = UsagePreferences::default();
+        up.parse(expr);
+        let before = up.clone();
+        up.minimize();
+        assert_eq!(up, before);
+        up
+    }
+
+    #[test]
+    fn minimize() {
+        let up = minimized("all=n,train-ai=n,train-genai=n,search=y,search=y");
+        assert_eq!(up.to_string(), "all=n,search=y");
+        assert_eq!(minimized("").to_string(), "");
+        assert_eq!(minimized("train-genai=y").to_string(), "train-genai=y");
+        assert_eq!(
+            minimized("all=y,train-ai=n,train-genai=n,ai-use=y,search=y").to_string(),
+            "all=y,train-ai=n"
+        );
+        // An allowance is needed where a more general label is denied.
+        assert_eq!(
+            minimized("all=n,train-ai=y,train-genai=y").to_string(),
+            "all=n,train-ai=y"
+        );
+    }
+
+    #[test]
+    fn multiple_parents() {
+        let mut v = Vocabulary::default();
+        v.add("a");
+        v.add("b");
+        v.add_with_parents("c", ["a", "b"]);
+        let mut up = Arc::new(v).preferences();
+        up.parse("a=y,b=n,c=n");
+        up.minimize();
+        assert_eq!(up.to_string(), "a=y,b=n");
+        up.parse("c=y");
+        up.minimize();
+        assert_eq!(up.to_string(), "a=y,b=n,c=y");
+    }
+
+    /// Check every combination of values for the default vocabulary.
+    #[test]
+    fn exhaustive() {
+        const STATES: [State; 3] = [State::Unknown, State::Yes, State::No];
+        let n = UsagePreferences::default().values.len();
+        for mut combination in 0..STATES.len().pow(u32::try_from(n).unwrap()) {
+            let mut up = UsagePreferences::default();
+            for v in &mut up.values {
+                *v = STATES[combination % STATES.len()];
+                combination /= STATES.len();
+            }
+            let before = up.clone();
+            up.minimize();
+            assert_eq!(up, before);
+
+            // No value can be removed without changing the outcome.
+            let set = (0..n)
+                .filter(|&i| up.values[i] != State::Unknown)
+                .collect::<Vec<_>>();
+            for i in set {
+                let mut fewer = up.clone();
+                fewer.values[i] = State::Unknown;
+                assert_ne!(fewer, before, "{before} minimized to {up}");
+            }
+        }
+    }
+}
@@ -0,0 +1,109 @@
+use alloc::vec::Vec;
+
+use crate::{UsagePreference, UsagePreferences};
+
+/// The labels that an application is classified under,
+/// each with the policy that applies when no preference is expressed for that label.
+///
+/// Use `UsagePreferences::determine` to evaluate preferences against a profile.
+#[derive(Default, Debug, Clone)]
+pub struct UsageProfile {
+    labels: Vec<(Vec<u8>, UsagePreference)>,
+}
+
+impl UsageProfile {
+    /// Create an empty profile.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a label that applies to the application,
+    /// with the default to use if no preference is expressed.
+    /// Adding the same label again replaces the default.
+    pub fn add(&mut self, usage: impl AsRef<[u8]>, dflt: UsagePreference) {
+        let usage = usage.as_ref();
+        if let Some(l) = self.labels.iter_mut().find(|(l, _)| l == usage) {
+            l.1 = dflt;
+        } else {
+            self.labels.push((usage.to_vec(), dflt));
+        }
+    }
+
+    /// Iterate over the labels in this profile and their defaults.
+    pub fn labels(&self) -> impl Iterator<Item = (&[u8], UsagePreference)> {
+        self.labels.iter().map(|(l, d)| (&l[..], *d))
+    }
+}
+
+impl UsagePreferences {
+    /// Determine whether the usage described by the profile is allowed.
+    ///
+    /// Each label is evaluated in turn, using its own default.
+    /// If any label is denied, the usage is denied.
+    /// An empty profile is allowed.
+    #[must_use]
+    pub fn determine(&self, profile: &UsageProfile) -> UsagePreference {
+        if profile
+            .labels()
+            .any(|(usage, dflt)| self.eval(usage, dflt) == UsagePreference::Denied)
+        {
+            UsagePreference::Denied
+        } else {
+            UsagePreference::Allowed
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::UsageProfile;
+    use crate::{
+        UsagePreference::{Allowed, Denied},
+        UsagePreferences,
+    };
+
+    /// An AI search answer engine.
+    fn answer_engine() -> UsageProfile {
+        let mut p = UsageProfile::new();
+        p.add(UsagePreferences::SEARCH, Allowed);
+        p.add(UsagePreferences::AI_USE, Denied);
+        p
+    }
+
+    #[test]
+    fn empty() {
+        let up = UsagePreferences::default();
+        assert_eq!(up.determine(&UsageProfile::new()), Allowed);
+    }
+
+    #[test]
+    fn per_label_default() {
+        let mut up = UsagePreferences::default();
+        assert_eq!(up.determine(&answer_engine()), Denied);
+        up.parse("ai-use=y");
+        assert_eq!(up.determine(&answer_engine()), Allowed);
+    }
+
+    #[test]
+    fn any_denied() {
+        let mut up = UsagePreferences::default();
+        up.parse("all=y,search=n");
+        assert_eq!(up.determine(&answer_engine()), Denied);
+    }
+
+    #[test]
+    fn cascade() {
+        let mut up = UsagePreferences::default();
+        up.parse("all=y");
+        assert_eq!(up.determine(&answer_engine()), Allowed);
+    }
+
+    #[test]
+    fn replace_default() {
+        let mut p = answer_engine();
+        p.add(UsagePreferences::AI_USE, Allowed);
+        assert_eq!(p.labels().count(), 2);
+        assert_eq!(UsagePreferences::default().determine(&p), Allowed);
+    }
+}
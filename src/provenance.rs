@@ -0,0 +1,141 @@
+use alloc::{string::String, vec::Vec};
+use core::ops::Range;
+
+use crate::{UsagePreference, UsagePreferences};
+
+/// The kind of carrier that a preference expression was obtained from.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum SourceKind {
+    /// A "Content-Usage" line in robots.txt, with its line number.
+    /// Spans are relative to the preference expression on that line,
+    /// not the start of the line.
+    RobotsTxt { line: usize },
+    /// The Content-Usage HTTP header field.
+    HttpHeader,
+    /// An HTML `<meta>` element.
+    HtmlMeta,
+    /// Metadata in a file.
+    FileMetadata,
+}
+
+/// Where a preference expression came from.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Source {
+    pub kind: SourceKind,
+    /// An identifier chosen by the caller, such as a host name or URL.
+    pub id: String,
+}
+
+impl Source {
+    #[must_use]
+    pub fn new(kind: SourceKind, id: impl Into<String>) -> Self {
+        Self {
+            kind,
+            id: id.into(),
+        }
+    }
+}
+
+/// A record of a value that was provided for a label.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Provenance {
+    pub source: Source,
+    pub value: UsagePreference,
+    /// The range of bytes in the expression that provided the value.
+    pub span: Range<usize>,
+}
+
+impl UsagePreferences {
+    /// Parse the provided input, as with `parse()`,
+    /// recording the source of each value that is found.
+    ///
+    /// Use `provenance()` to retrieve what is recorded.
+    pub fn parse_from(&mut self, expr: impl AsRef<[u8]>, source: &Source) {
+        _ = self.parse_inner(
+            expr.as_ref(),
+            &mut crate::Reporter {
+                diagnostics: None,
+                source: Some(source),
+            },
+        );
+    }
+
+    /// Get the values that were provided for the given usage
+    /// by calls to `parse_from()`, in the order they were found.
+    ///
+    /// After `parse_from()`, this only includes values that were set for this usage.
+    /// After `merge()`, it also includes the provenance of the value
+    /// that the other preferences had for this usage,
+    /// which might have been inherited there from a more general usage.
+    /// Note that this includes values that did not take effect
+    /// because another value took precedence.
+    ///
+    /// Values for labels that are not in the vocabulary,
+    /// which are kept if `set_preserve_unknown()` is used, have no provenance.
+    #[must_use]
+    pub fn provenance(&self, usage: impl AsRef<[u8]>) -> &[Provenance] {
+        self.index_of(usage.as_ref())
+            .and_then(|i| self.provenance.get(i))
+            .map_or(&[], Vec::as_slice)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Provenance, Source, SourceKind};
+    use crate::{
+        UsagePreference::{Allowed, Denied},
+        UsagePreferences, UsagePreferencesAssertions,
+    };
+
+    #[test]
+    fn parse_from() {
+        let header = Source::new(SourceKind::HttpHeader, "https://example.com/a");
+        let mut up = UsagePreferences::default();
+        up.parse_from("all=y, search=n, search=y", &header);
+        up.assert_denied(UsagePreferences::SEARCH);
+        assert_eq!(
+            up.provenance(UsagePreferences::ALL),
+            [Provenance {
+                source: header.clone(),
+                value: Allowed,
+                span: 0..5,
+            }]
+        );
+        let search = up.provenance(UsagePreferences::SEARCH);
+        assert_eq!(search.len(), 2);
+        assert_eq!(search[0].value, Denied);
+        assert_eq!(search[0].span, 7..15);
+        assert_eq!(search[1].value, Allowed);
+        assert_eq!(search[1].span, 17..25);
+        assert!(up.provenance(UsagePreferences::TRAIN_AI).is_empty());
+        assert!(up.provenance("unknown").is_empty());
+    }
+
+    #[test]
+    fn parse_untracked() {
+        let mut up = UsagePreferences::default();
+        up.parse("all=y");
+        up.assert_allowed(UsagePreferences::ALL);
+        assert!(up.provenance(UsagePreferences::ALL).is_empty());
+    }
+
+    #[test]
+    fn merge() {
+        let meta = Source::new(SourceKind::HtmlMeta, "a");
+        let file = Source::new(SourceKind::FileMetadata, "b");
+        let mut up1 = UsagePreferences::default();
+        up1.parse_from("train-ai=y", &meta);
+        let mut up2 = UsagePreferences::default();
+        up2.parse_from("all=n", &file);
+        up1.merge(&up2);
+        up1.assert_denied(UsagePreferences::TRAIN_AI);
+
+        let train = up1.provenance(UsagePreferences::TRAIN_AI);
+        assert_eq!(train.len(), 2);
+        assert_eq!(train[0].source, meta);
+        assert_eq!(train[1].source, file);
+        assert_eq!(train[1].value, Denied);
+        assert_eq!(up1.provenance(UsagePreferences::ALL)[0].source, file);
+    }
+}
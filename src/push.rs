@@ -0,0 +1,461 @@
+use alloc::vec::Vec;
+use core::ops::Range;
+
+use crate::{
+    Diagnostic, DiagnosticKind, ParseLimits, Reporter, Source, State, Target, UsagePreferences,
+};
+
+/// Which part of a member is being parsed.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+enum Phase {
+    Label,
+    Value,
+    /// The rest of the member is ignored.
+    Skip,
+}
+
+/// What has been found for the value of a member.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+enum Value {
+    Empty,
+    Set(State),
+    Invalid,
+}
+
+/// The state needed to parse an expression incrementally.
+///
+/// This follows the loose syntax from the draft.
+/// The only input that is retained is the label of the current member,
+/// up to the length of the longest known label.
+/// If labels that are not in the vocabulary are preserved,
+/// labels up to the limit on the length of an expression are retained instead.
+#[derive(Debug)]
+pub(crate) struct Machine {
+    limits: ParseLimits,
+    phase: Phase,
+    /// The offset of the next byte of input.
+    offset: usize,
+    /// The number of directives that have been completed.
+    directives: usize,
+    /// The offset of the comma that ended the last directive, or 0.
+    last_comma: usize,
+    /// Where processing stopped, if a limit was reached.
+    stopped: Option<usize>,
+
+    label: Vec<u8>,
+    /// Whitespace that might be inside the label.
+    whitespace: Vec<u8>,
+    /// The label is longer than can be retained.
+    too_long: bool,
+    label_span: Option<Range<usize>>,
+
+    /// The index of the label, or `None` for a label that is not in the vocabulary.
+    item: Option<usize>,
+    value: Value,
+    value_span: Option<Range<usize>>,
+}
+
+impl Machine {
+    pub(crate) fn new(limits: ParseLimits) -> Self {
+        let stopped = (limits.expression_len == 0 || limits.directives == 0).then_some(0);
+        Self {
+            limits,
+            phase: Phase::Label,
+            offset: 0,
+            directives: 0,
+            last_comma: 0,
+            stopped,
+            label: Vec::new(),
+            whitespace: Vec::new(),
+            too_long: false,
+            label_span: None,
+            item: None,
+            value: Value::Empty,
+            value_span: None,
+        }
+    }
+
+    fn extend(span: &mut Option<Range<usize>>, i: usize) {
+        span.get_or_insert(i..i).end = i + 1;
+    }
+
+    /// How much of a label to keep.
+    /// Labels that are not in the vocabulary are only kept if they are being preserved,
+    /// in which case the limit on the length of an expression applies.
+    fn max_label(&self, prefs: &UsagePreferences) -> usize {
+        if prefs.preserve_unknown {
+            self.limits.expression_len
+        } else {
+            prefs.vocabulary.max_len
+        }
+    }
+
+    fn push_label(&mut self, prefs: &UsagePreferences, i: usize, c: u8) {
+        Self::extend(&mut self.label_span, i);
+        if self.too_long {
+            return;
+        }
+        if self.label.len() + self.whitespace.len() < self.max_label(prefs) {
+            self.label.append(&mut self.whitespace);
+            self.label.push(c);
+        } else {
+            self.too_long = true;
+        }
+    }
+
+    fn push_whitespace(&mut self, prefs: &UsagePreferences, c: u8) {
+        // Whitespace is only kept if there is room for another character after it.
+        if self.label_span.is_some()
+            && self.label.len() + self.whitespace.len() < self.max_label(prefs)
+        {
+            self.whitespace.push(c);
+        }
+    }
+
+    /// Handle the '=' that ends a label.
+    fn end_label(&mut self, prefs: &UsagePreferences, report: &mut Reporter<'_>, i: usize) {
+        self.phase = Phase::Skip;
+        let Some(span) = self.label_span.clone() else {
+            report.report(DiagnosticKind::MalformedMember, i..i);
+            return;
+        };
+        if self.too_long {
+            report.report(DiagnosticKind::UnknownLabel, span);
+            return;
+        }
+        match prefs.target(&self.label) {
+            Some(Target::Label(item)) => {
+                self.item = Some(item);
+                self.phase = Phase::Value;
+            }
+            Some(Target::Unknown(_)) => {
+                report.report(DiagnosticKind::UnknownLabel, span);
+                self.item = None;
+                self.phase = Phase::Value;
+            }
+            None => report.report(DiagnosticKind::UnknownLabel, span),
+        }
+    }
+
+    /// Handle the ',' or the end of input that ends a member.
+    fn end_member(&mut self, prefs: &mut UsagePreferences, report: &mut Reporter<'_>, i: usize) {
+        match self.phase {
+            Phase::Label => {
+                if let Some(span) = self.label_span.clone() {
+                    report.report(DiagnosticKind::MalformedMember, span);
+                }
+            }
+            Phase::Value => match self.value {
+                Value::Set(v) => {
+                    let start = self.label_span.as_ref().map_or(i, |s| s.start);
+                    let end = self.value_span.as_ref().map_or(i, |s| s.end);
+                    let target = self
+                        .item
+                        .map_or(Target::Unknown(&self.label), Target::Label);
+                    report.apply(prefs, target, v, start..end);
+                }
+                Value::Empty => report.report(DiagnosticKind::InvalidValue, i..i),
+                Value::Invalid => {
+                    let span = self.value_span.clone().unwrap_or(i..i);
+                    report.report(DiagnosticKind::InvalidValue, span);
+                }
+            },
+            Phase::Skip => {}
+        }
+        self.phase = Phase::Label;
+        self.label.clear();
+        self.whitespace.clear();
+        self.too_long = false;
+        self.label_span = None;
+        self.value = Value::Empty;
+        self.value_span = None;
+    }
+
+    pub(crate) fn feed(
+        &mut self,
+        prefs: &mut UsagePreferences,
+        report: &mut Reporter<'_>,
+        chunk: &[u8],
+    ) {
+        for &c in chunk {
+            let i = self.offset;
+            self.offset += 1;
+            if self.stopped.is_some() {
+                continue;
+            }
+            if c != b',' && i >= self.limits.expression_len {
+                // This directive does not end within the limit, so it is discarded.
+                self.stopped = Some(self.last_comma);
+                continue;
+            }
+            if c == b',' {
+                self.end_member(prefs, report, i);
+                self.directives += 1;
+                self.last_comma = i;
+                if self.directives >= self.limits.directives || i >= self.limits.expression_len {
+                    self.stopped = Some(i);
+                }
+                continue;
+            }
+            match self.phase {
+                Phase::Label => {
+                    if c == b'=' {
+                        self.end_label(prefs, report, i);
+                    } else if c.is_ascii_whitespace() {
+                        self.push_whitespace(prefs, c);
+                    } else {
+                        self.push_label(prefs, i, c);
+                    }
+                }
+                Phase::Value => {
+                    if !c.is_ascii_whitespace() {
+                        Self::extend(&mut self.value_span, i);
+                        self.value = match (self.value, c) {
+                            (Value::Empty, b'y') => Value::Set(State::Yes),
+                            (Value::Empty, b'n') => Value::Set(State::No),
+                            _ => Value::Invalid,
+                        };
+                    }
+                }
+                Phase::Skip => {}
+            }
+        }
+    }
+
+    /// Process the end of input.
+    pub(crate) fn finish(&mut self, prefs: &mut UsagePreferences, report: &mut Reporter<'_>) {
+        if let Some(stopped) = self.stopped {
+            if stopped < self.offset {
+                report.report(DiagnosticKind::Truncated, stopped..self.offset);
+            }
+        } else {
+            self.end_member(prefs, report, self.offset);
+        }
+    }
+}
+
+/// A parser that takes a preference expression in pieces.
+///
+/// Input is provided with `feed()`, which can be called any number of times;
+/// any split, even one that falls inside a label, produces the same outcome.
+/// Values are added to the preferences as each directive is completed,
+/// but the last directive is only processed when `finish()` is called.
+///
+/// This always uses the loose syntax, even when the "sfv" feature is enabled.
+/// The limits set on the preferences apply.
+pub struct ExpressionParser<'a> {
+    prefs: &'a mut UsagePreferences,
+    report: Reporter<'a>,
+    machine: Machine,
+}
+
+impl<'a> ExpressionParser<'a> {
+    #[must_use]
+    pub fn new(prefs: &'a mut UsagePreferences) -> Self {
+        let machine = Machine::new(prefs.limits());
+        Self {
+            prefs,
+            report: Reporter {
+                diagnostics: None,
+                source: None,
+            },
+            machine,
+        }
+    }
+
+    /// Collect diagnostics, as `UsagePreferences::parse_with_diagnostics()` does.
+    #[must_use]
+    pub fn with_diagnostics(mut self, diagnostics: &'a mut Vec<Diagnostic>) -> Self {
+        self.report.diagnostics = Some(diagnostics);
+        self
+    }
+
+    /// Record the source of values, as `UsagePreferences::parse_from()` does.
+    #[must_use]
+    pub fn with_source(mut self, source: &'a Source) -> Self {
+        self.report.source = Some(source);
+        self
+    }
+
+    /// Process the next piece of input.
+    pub fn feed(&mut self, chunk: impl AsRef<[u8]>) {
+        self.machine
+            .feed(self.prefs, &mut self.report, chunk.as_ref());
+    }
+
+    /// Complete processing of the input.
+    pub fn finish(mut self) {
+        self.machine.finish(self.prefs, &mut self.report);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use alloc::{string::ToString, vec::Vec};
+
+    use super::ExpressionParser;
+    use crate::{
+        Diagnostic, DiagnosticKind, ParseLimits, UsagePreferences, UsagePreferencesAssertions,
+    };
+
+    /// Parse in two pieces, split at each possible position.
+    /// Check that the outcome is the same every time.
+    fn all_splits(expr: &str) -> (UsagePreferences, Vec<Diagnostic>) {
+        let mut first = None;
+        for split in 0..=expr.len() {
+            let mut up = UsagePreferences::default();
+            let mut diagnostics = Vec::new();
+            let mut p = ExpressionParser::new(&mut up).with_diagnostics(&mut diagnostics);
+            p.feed(&expr.as_bytes()[..split]);
+            p.feed(&expr.as_bytes()[split..]);
+            p.finish();
+            let result = (up.to_string(), diagnostics.clone());
+            if let Some(first) = &first {
+                assert_eq!(first, &result, "split at {split}");
+            } else {
+                first = Some(result);
+            }
+            if split == expr.len() {
+                return (up, diagnostics);
+            }
+        }
+        unreachable!();
+    }
+
+    #[test]
+    fn split_label() {
+        let (up, d) = all_splits("train-genai=y, search = n ,all=y");
+        assert_eq!(d, []);
+        up.assert_allowed(UsagePreferences::ALL);
+        up.assert_allowed(UsagePreferences::TRAIN_GENAI);
+        up.assert_allowed(UsagePreferences::AI_USE);
+        up.assert_denied(UsagePreferences::SEARCH);
+    }
+
+    #[test]
+    fn byte_at_a_time() {
+        let mut up = UsagePreferences::default();
+        let mut p = ExpressionParser::new(&mut up);
+        for c in b"ai-use=n,train-ai=y" {
+            p.feed([*c]);
+        }
+        p.finish();
+        up.assert_denied(UsagePreferences::AI_USE);
+        up.assert_allowed(UsagePreferences::TRAIN_AI);
+    }
+
+    #[test]
+    fn no_finish() {
+        let mut up = UsagePreferences::default();
+        let mut p = ExpressionParser::new(&mut up);
+        p.feed("all=n,search=y");
+        drop(p);
+        up.assert_denied(UsagePreferences::ALL);
+        up.assert_denied(UsagePreferences::SEARCH);
+    }
+
+    #[test]
+    fn diagnostics() {
+        let (up, d) = all_splits(" all , =y,, searching=n,search = n ,train-ai=y n,ai-use=");
+        assert_eq!(
+            d,
+            [
+                Diagnostic {
+                    kind: DiagnosticKind::MalformedMember,
+                    span: 1..4,
+                },
+                Diagnostic {
+                    kind: DiagnosticKind::MalformedMember,
+                    span: 7..7,
+                },
+                Diagnostic {
+                    kind: DiagnosticKind::UnknownLabel,
+                    span: 12..21,
+                },
+                Diagnostic {
+                    kind: DiagnosticKind::InvalidValue,
+                    span: 45..48,
+                },
+                Diagnostic {
+                    kind: DiagnosticKind::InvalidValue,
+                    span: 56..56,
+                },
+            ]
+        );
+        up.assert_denied(UsagePreferences::SEARCH);
+        up.assert_unset(UsagePreferences::TRAIN_AI);
+    }
+
+    #[test]
+    fn whitespace_in_label() {
+        let mut up = UsagePreferences::blank();
+        up.add("a b");
+        up.add("c");
+        up.parse("a   b=y");
+        let mut p = ExpressionParser::new(&mut up);
+        p.feed("  a b   ");
+        p.feed("  =  n, c          ");
+        p.feed("   =y");
+        p.finish();
+        up.assert_denied("a b");
+        up.assert_allowed("c");
+    }
+
+    #[test]
+    fn preserve_unknown() {
+        let mut up = UsagePreferences::default();
+        up.set_preserve_unknown(true);
+        let mut diagnostics = Vec::new();
+        let mut p = ExpressionParser::new(&mut up).with_diagnostics(&mut diagnostics);
+        p.feed(b"a-label-longer-than-any-known=y, search=n,");
+        p.feed(b" tdm=n, tdm=y");
+        p.finish();
+        assert_eq!(
+            up.to_string(),
+            "search=n,a-label-longer-than-any-known=y,tdm=n"
+        );
+        assert_eq!(diagnostics.len(), 3);
+        assert!(
+            diagnostics
+                .iter()
+                .all(|d| d.kind == DiagnosticKind::UnknownLabel)
+        );
+    }
+
+    #[test]
+    fn preserve_unknown_limit() {
+        let mut up = UsagePreferences::default();
+        up.set_preserve_unknown(true);
+        up.set_limits(ParseLimits::RECOMMENDED);
+        let mut p = ExpressionParser::new(&mut up);
+        for _ in 0..100 {
+            p.feed([b'a'; 100]);
+        }
+        assert!(p.machine.label.len() <= ParseLimits::RECOMMENDED.expression_len);
+        p.feed(b"=y");
+        p.finish();
+        assert_eq!(up.unknown_labels().count(), 0);
+    }
+
+    #[test]
+    fn limits() {
+        let mut up = UsagePreferences::default();
+        up.set_limits(ParseLimits {
+            directives: 1,
+            ..ParseLimits::NONE
+        });
+        let mut d = Vec::new();
+        let mut p = ExpressionParser::new(&mut up).with_diagnostics(&mut d);
+        p.feed("all=y,se");
+        p.feed("arch=n");
+        p.finish();
+        assert_eq!(
+            d,
+            [Diagnostic {
+                kind: DiagnosticKind::Truncated,
+                span: 5..14,
+            }]
+        );
+        up.assert_allowed(UsagePreferences::SEARCH);
+    }
+}
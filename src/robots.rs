@@ -1,27 +1,73 @@
-use std::{
-    cmp::Ordering::{Equal, Greater, Less},
-    io::{BufRead, Result},
-};
+use alloc::{borrow::Cow, string::String, vec::Vec};
+use core::cmp::Ordering::{Equal, Greater, Less};
+use std::io::{BufRead, Result};
 
-use crate::UsagePreferences;
+use crate::{ParseLimits, Reporter, Source, SourceKind, UsagePreferences};
+
+/// Normalize the percent-encoding of a path or pattern, as Section 2.2.2 of RFC 9309 requires.
+///
+/// Unreserved characters that are percent-encoded are decoded,
+/// other percent-encoded characters use uppercase hexadecimal digits,
+/// and characters outside of ASCII are encoded.
+/// This does not change '*' or '$', so it can be applied to patterns.
+fn normalize_path(path: &str) -> String {
+    const HEX: &[u8; 16] = b"0123456789ABCDEF";
+    fn encode(out: &mut String, b: u8) {
+        out.push('%');
+        out.push(char::from(HEX[usize::from(b >> 4)]));
+        out.push(char::from(HEX[usize::from(b & 0xf)]));
+    }
+    fn hex(c: u8) -> Option<u8> {
+        char::from(c)
+            .to_digit(16)
+            .and_then(|d| u8::try_from(d).ok())
+    }
+
+    let bytes = path.as_bytes();
+    let mut out = String::with_capacity(path.len());
+    let mut i = 0;
+    while let Some(&b) = bytes.get(i) {
+        i += 1;
+        if b == b'%'
+            && let Some(hi) = bytes.get(i).copied().and_then(hex)
+            && let Some(lo) = bytes.get(i + 1).copied().and_then(hex)
+        {
+            i += 2;
+            let decoded = (hi << 4) | lo;
+            if decoded.is_ascii_alphanumeric() || b"-._~".contains(&decoded) {
+                out.push(char::from(decoded));
+            } else {
+                encode(&mut out, decoded);
+            }
+        } else if b.is_ascii() {
+            out.push(char::from(b));
+        } else {
+            encode(&mut out, b);
+        }
+    }
+    out
+}
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 struct ContentUsageLine {
-    #[allow(dead_code, reason = "Tracking this for debugging purposes")]
-    line: usize,
     path: String,
     usage: UsagePreferences,
 }
 
 impl ContentUsageLine {
-    fn new(line: usize, path: String, usage: UsagePreferences) -> Self {
-        Self { line, path, usage }
+    fn new(path: String, usage: UsagePreferences) -> Self {
+        Self { path, usage }
     }
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 struct AdmissionLine {
-    #[allow(dead_code, reason = "Tracking this for debugging purposes")]
+    #[cfg_attr(
+        not(feature = "serde"),
+        allow(dead_code, reason = "Tracking this for debugging purposes")
+    )]
     line: usize,
     allow: bool,
     path: String,
@@ -42,6 +88,7 @@ impl AdmissionLine {
 }
 
 #[derive(Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 struct Group {
     line: usize,
     user_agents: Vec<String>,
@@ -51,29 +98,47 @@ struct Group {
 
 impl Group {
     /// Take a loosely-parsed line and integrate it into this group.
-    fn parse_line(&mut self, line: usize, name: &str, value: &str) {
+    /// Preferences start from a copy of `blank`.
+    /// Provenance is only recorded if there is an `id`.
+    /// Returns true if a preference expression was truncated.
+    fn parse_line(
+        &mut self,
+        line: usize,
+        name: &str,
+        value: &str,
+        id: Option<&str>,
+        blank: &UsagePreferences,
+    ) -> bool {
         if name.eq_ignore_ascii_case("content-usage") {
-            {
-                let (path, expr) = if value.starts_with('/') {
-                    let Some((path, expr)) = value.split_once(&[' ', '\t']) else {
-                        return;
-                    };
-                    (path, expr)
-                } else {
-                    ("", value)
+            let (path, expr) = if value.starts_with('/') {
+                let Some((path, expr)) = value.split_once([' ', '\t']) else {
+                    return false;
                 };
-                let mut usage = UsagePreferences::default();
-                usage.parse(expr);
-                self.usage_preferences
-                    .push(ContentUsageLine::new(line, path.to_string(), usage));
+                (path, expr)
+            } else {
+                ("", value)
             };
+            let mut usage = blank.clone();
+            let truncated = usage.parse_inner(
+                expr.as_bytes(),
+                &mut Reporter {
+                    diagnostics: None,
+                    source: id
+                        .map(|id| Source::new(SourceKind::RobotsTxt { line }, id))
+                        .as_ref(),
+                },
+            );
+            self.usage_preferences
+                .push(ContentUsageLine::new(normalize_path(path), usage));
+            return truncated;
         } else if name.eq_ignore_ascii_case("allow") {
             self.admissions
-                .push(AdmissionLine::new(line, true, value.to_string()));
+                .push(AdmissionLine::new(line, true, normalize_path(value)));
         } else if name.eq_ignore_ascii_case("disallow") {
             self.admissions
-                .push(AdmissionLine::new(line, false, value.to_string()));
+                .push(AdmissionLine::new(line, false, normalize_path(value)));
         }
+        false
     }
 
     /// Performs path matching according to the special character rules
@@ -82,15 +147,9 @@ impl Group {
     /// it therefore only handles the end-of-pattern ('$') and
     /// wildcard ('*').
     fn path_match(pattern: &str, path: &str) -> bool {
-        let (pattern, complete) = if let Some(p) = pattern.strip_suffix('$') {
-            if p.ends_with('*') {
-                // A path of "/whatever*$" is pointless.
-                (p.trim_end_matches('*'), false)
-            } else {
-                (p, true)
-            }
-        } else {
-            (pattern, false)
+        let (pattern, anchored) = match pattern.strip_suffix('$') {
+            Some(p) => (p, true),
+            None => (pattern, false),
         };
         let mut chunks = pattern.split('*');
         let Some(first) = chunks.next() else {
@@ -99,31 +158,57 @@ impl Group {
         let Some(mut remainder) = path.strip_prefix(first) else {
             return false;
         };
+        let Some(last) = chunks.next_back() else {
+            // No wildcard.
+            return !anchored || remainder.is_empty();
+        };
+        // Matching each chunk at its first occurrence leaves the most room for the rest.
+        // That only works for the last chunk if the pattern is not anchored to the end.
+        // Each search only examines the input that follows the previous match,
+        // so this takes linear time.
         for c in chunks {
             let Some(offset) = remainder.find(c) else {
                 return false;
             };
             remainder = &remainder[offset + c.len()..];
         }
-        !complete || remainder.is_empty()
+        if anchored {
+            remainder.ends_with(last)
+        } else {
+            remainder.contains(last)
+        }
     }
 
     /// Determine whether Allow/Disallow rules allow crawling of the given path.
     /// This operates across multiple groups, so that the lines that apply are all effectively
     /// merged into a single group.
+    ///
+    /// As RFC 9309 requires, a path that no rule matches is allowed,
+    /// a rule with an empty path matches nothing,
+    /// and "/robots.txt" is always allowed.
     fn is_admitted<'a>(groups: impl Iterator<Item = &'a Self>, path: &str) -> bool {
-        let mut current = AdmissionLine::new(0, false, String::new());
+        if path == "/robots.txt" {
+            return true;
+        }
+        let mut current: Option<&AdmissionLine> = None;
         for a in groups.flat_map(|g| &g.admissions) {
-            if Self::path_match(&a.path, path) && a.is_more_specific(&current) {
-                current = a.clone();
+            if !a.path.is_empty()
+                && Self::path_match(&a.path, path)
+                && current.is_none_or(|c| a.is_more_specific(c))
+            {
+                current = Some(a);
             }
         }
-        current.allow
+        current.is_none_or(|a| a.allow)
     }
 
     /// Obtains preferences for the given path across the provided groups.
-    fn preferences<'a>(groups: impl Iterator<Item = &'a Self>, path: &str) -> UsagePreferences {
-        let mut prefs = UsagePreferences::default();
+    fn preferences<'a>(
+        groups: impl Iterator<Item = &'a Self>,
+        path: &str,
+        blank: &UsagePreferences,
+    ) -> UsagePreferences {
+        let mut prefs = blank.clone();
         let mut len = 0;
         let mut matching = Vec::new();
         for p in groups.flat_map(|g| &g.usage_preferences) {
@@ -132,38 +217,170 @@ impl Group {
                     Greater => {
                         matching.truncate(0);
                         len = p.path.len();
-                        matching.push(p.clone());
+                        matching.push(p);
                     }
-                    Equal => matching.push(p.clone()),
+                    Equal => matching.push(p),
                     Less => {}
                 }
             }
         }
-        for m in &matching {
+        for m in matching {
             prefs.merge(&m.usage);
         }
         prefs
     }
 }
 
+/// How the groups that apply to a user agent are chosen.
+#[derive(Default, PartialEq, Eq, Clone, Copy, Debug)]
+pub enum GroupSelection {
+    /// Use the groups that name the user agent, as RFC 9309 requires.
+    /// Only if there are none are the groups for "*" used.
+    #[default]
+    Rfc9309,
+    /// Use the groups that name the user agent if they permit crawling of the path.
+    /// Otherwise, use the groups for "*".
+    /// This means that the groups for "*" can permit crawling
+    /// that the groups for the user agent do not.
+    Fallback,
+}
+
+/// Read the content of a line into `buf`, without the line ending,
+/// which can be LF, CR, or CRLF.
+/// At most `limit` bytes of content are retained,
+/// and reading stops after `max_read` bytes, even if the line has not ended.
+/// Returns the number of bytes that were read, including the line ending,
+/// and the length of the content.
+fn read_line(
+    input: &mut impl BufRead,
+    buf: &mut Vec<u8>,
+    limit: usize,
+    max_read: usize,
+) -> Result<(usize, usize)> {
+    let mut read = 0;
+    let mut len = 0;
+    while read < max_read {
+        let available = input.fill_buf()?;
+        if available.is_empty() {
+            break;
+        }
+        let available = &available[..available.len().min(max_read - read)];
+        let (content, end) = available
+            .iter()
+            .position(|&c| c == b'\n' || c == b'\r')
+            .map_or((available.len(), None), |i| (i, Some(available[i])));
+        let room = limit.saturating_sub(buf.len());
+        buf.extend_from_slice(&available[..content.min(room)]);
+        len += content;
+        let used = content + usize::from(end.is_some());
+        input.consume(used);
+        read += used;
+        match end {
+            Some(b'\r') => {
+                // The LF in a CRLF might not have been read yet.
+                if input.fill_buf()?.first() == Some(&b'\n') {
+                    input.consume(1);
+                    read += 1;
+                }
+                break;
+            }
+            Some(_) => break,
+            None => {}
+        }
+    }
+    Ok((read, len))
+}
+
+/// The groups and rules read from a robots.txt file.
+///
+/// With the "serde" feature, this can be serialized, for reporting, but not deserialized.
+/// That is deliberate: the limits and group selection are not included,
+/// and preferences need a vocabulary to be read back, as with `UsagePreferences`.
+/// Parse the file again to get an equivalent value.
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct Robots {
     groups: Vec<Group>,
+    truncated: bool,
+    invalid_utf8_lines: Vec<usize>,
+    /// Preferences with no values, which all others are copied from,
+    /// so that they all share a single vocabulary.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    blank: UsagePreferences,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    selection: GroupSelection,
 }
 
 impl Robots {
-    pub fn parse(mut input: impl BufRead) -> Result<Self> {
-        let mut r = Self { groups: Vec::new() };
+    pub fn parse(input: impl BufRead) -> Result<Self> {
+        Self::parse_with_limits(input, None, ParseLimits::NONE)
+    }
+
+    /// Parse robots.txt, as with `parse()`.
+    /// The preferences this produces record the line that each value came from,
+    /// identifying the file with `id`; see `UsagePreferences::provenance()`.
+    pub fn parse_from(input: impl BufRead, id: &str) -> Result<Self> {
+        Self::parse_with_limits(input, Some(id), ParseLimits::NONE)
+    }
+
+    /// Parse robots.txt, subject to the given limits.
+    /// Use `is_truncated()` to learn whether any limit was reached.
+    /// Provenance is recorded if `id` is provided, as with `parse_from()`.
+    ///
+    /// Lines can end with LF, CR, or CRLF, and a leading byte order mark is ignored.
+    /// Bytes that are not valid UTF-8 are replaced, rather than causing an error;
+    /// see `invalid_utf8_lines()`.
+    pub fn parse_with_limits(
+        mut input: impl BufRead,
+        id: Option<&str>,
+        limits: ParseLimits,
+    ) -> Result<Self> {
+        let mut blank = UsagePreferences::default();
+        blank.set_limits(limits);
+        let mut r = Self {
+            groups: Vec::new(),
+            truncated: false,
+            invalid_utf8_lines: Vec::new(),
+            blank,
+            selection: GroupSelection::default(),
+        };
         let mut group = Group::default();
         let mut line = 0;
         let mut ua = false;
+        let mut size = 0_usize;
 
-        let mut buf = String::new();
-        while input.read_line(&mut buf)? > 0 {
+        let mut bytes = Vec::new();
+        loop {
+            bytes.truncate(0);
+            // Read one byte more than the limit allows, so that exceeding it can be detected.
+            let budget = limits.robots_size.saturating_sub(size).saturating_add(1);
+            let (read, len) = read_line(&mut input, &mut bytes, limits.robots_line_len, budget)?;
+            if read == 0 {
+                break;
+            }
             line += 1;
+            size = size.saturating_add(read);
+            if size > limits.robots_size {
+                r.truncated = true;
+                break;
+            }
+            if len > bytes.len() {
+                // Part of a line could change its meaning, so ignore it all.
+                r.truncated = true;
+                continue;
+            }
+            let mut content = &bytes[..];
+            if line == 1 {
+                content = content.strip_prefix(b"\xef\xbb\xbf").unwrap_or(content);
+            }
+            let buf = String::from_utf8_lossy(content);
+            if matches!(buf, Cow::Owned(_)) {
+                r.invalid_utf8_lines.push(line);
+            }
+            let buf = &*buf;
             if let Some((name, value)) = buf
                 .split_once('#')
                 .map(|(a, _b)| a)
-                .unwrap_or(&buf)
+                .unwrap_or(buf)
                 .split_once(':')
                 .map(|(a, b)| (a.trim_ascii(), b.trim_ascii()))
             {
@@ -181,16 +398,39 @@ impl Robots {
                     group.user_agents.push(value.to_ascii_lowercase());
                 } else {
                     ua = false;
-                    group.parse_line(line, name, value);
+                    r.truncated |= group.parse_line(line, name, value, id, &r.blank);
                 }
             }
-            buf.truncate(0);
         }
         r.groups.push(group);
         Ok(r)
     }
 
-    fn groups(&self, user_agent: &str) -> impl Iterator<Item = &Group> {
+    /// Whether any part of the input was not processed because a limit was reached.
+    #[must_use]
+    pub fn is_truncated(&self) -> bool {
+        self.truncated
+    }
+
+    /// The lines, counting from 1, that include bytes that are not valid UTF-8.
+    /// Those bytes were replaced with U+FFFD before the line was processed.
+    #[must_use]
+    pub fn invalid_utf8_lines(&self) -> &[usize] {
+        &self.invalid_utf8_lines
+    }
+
+    /// Choose how the groups that apply to a user agent are found.
+    pub fn set_group_selection(&mut self, selection: GroupSelection) {
+        self.selection = selection;
+    }
+
+    /// How the groups that apply to a user agent are found.
+    #[must_use]
+    pub fn group_selection(&self) -> GroupSelection {
+        self.selection
+    }
+
+    fn groups(&self, user_agent: &str) -> impl Iterator<Item = &Group> + Clone {
         self.groups.iter().filter(move |g| {
             g.user_agents
                 .iter()
@@ -200,6 +440,8 @@ impl Robots {
 
     /// Determine the preferences that apply to a given user agent for a specific path.
     ///
+    /// The groups that apply are chosen as `group_selection()` determines.
+    ///
     /// # Returns
     /// An option, which is `Some` when crawling is permitted,
     /// including a value that can be interrogated regarding preferences.
@@ -209,12 +451,25 @@ impl Robots {
         path: impl AsRef<str>,
     ) -> Option<UsagePreferences> {
         let user_agent = user_agent.as_ref().to_ascii_lowercase();
-        let path = path.as_ref();
+        let path = &normalize_path(path.as_ref());
+
+        if self.selection == GroupSelection::Rfc9309 {
+            let mut groups = self.groups(&user_agent);
+            if groups.clone().next().is_none() {
+                groups = self.groups("*");
+            }
+            return Group::is_admitted(groups.clone(), path)
+                .then(|| Group::preferences(groups, path, &self.blank));
+        }
 
         if Group::is_admitted(self.groups(&user_agent), path) {
-            Some(Group::preferences(self.groups(&user_agent), path))
+            Some(Group::preferences(
+                self.groups(&user_agent),
+                path,
+                &self.blank,
+            ))
         } else if Group::is_admitted(self.groups("*"), path) {
-            Some(Group::preferences(self.groups("*"), path))
+            Some(Group::preferences(self.groups("*"), path, &self.blank))
         } else {
             None
         }
@@ -223,7 +478,92 @@ impl Robots {
 
 #[cfg(test)]
 mod test {
-    use crate::{UsagePreferences, UsagePreferencesAssertions, robots::Robots};
+    use alloc::{format, string::String, sync::Arc, vec, vec::Vec};
+    use std::io::{BufReader, Read};
+
+    use crate::{
+        ParseLimits, Source, SourceKind,
+        UsagePreference::Denied,
+        UsagePreferences, UsagePreferencesAssertions,
+        robots::{Group, GroupSelection, Robots},
+    };
+
+    /// A simple implementation of path matching that tries every option.
+    fn path_match_reference(pattern: &[u8], path: &[u8]) -> bool {
+        match pattern {
+            [] => true,
+            [b'$'] => path.is_empty(),
+            [b'*', rest @ ..] => (0..=path.len()).any(|i| path_match_reference(rest, &path[i..])),
+            [c, rest @ ..] => path
+                .split_first()
+                .is_some_and(|(p, path)| p == c && path_match_reference(rest, path)),
+        }
+    }
+
+    /// Every string of up to `len` characters from `alphabet`.
+    fn strings(alphabet: &[u8], len: usize) -> Vec<String> {
+        let mut all = vec![String::new()];
+        let mut last = all.clone();
+        for _ in 0..len {
+            last = last
+                .iter()
+                .flat_map(|s| {
+                    alphabet
+                        .iter()
+                        .map(move |&c| format!("{s}{}", char::from(c)))
+                })
+                .collect();
+            all.extend_from_slice(&last);
+        }
+        all
+    }
+
+    #[test]
+    fn path_match() {
+        for (pattern, path, expected) in [
+            ("/*.jpg$", "/a.jpg.jpg", true),
+            ("/*.jpg$", "/a.jpg", true),
+            ("/*.jpg$", "/a.jpg?x", false),
+            ("/*.jpg", "/a.jpg?x", true),
+            ("/a$", "/a", true),
+            ("/a$", "/ab", false),
+            ("/*a*$", "/ba", true),
+            ("/*aa$", "/aaa", true),
+            ("/*ab*ab$", "/abab", true),
+            ("/*ab*ab$", "/aba", false),
+            ("$", "", true),
+            ("$", "/", false),
+            ("*$", "/anything", true),
+        ] {
+            assert_eq!(
+                Group::path_match(pattern, path),
+                expected,
+                "{pattern} on {path}"
+            );
+        }
+
+        let paths = strings(b"/ab", 5);
+        for pattern in strings(b"/ab*", 5) {
+            for pattern in [pattern.clone(), format!("{pattern}$")] {
+                for path in &paths {
+                    assert_eq!(
+                        Group::path_match(&pattern, path),
+                        path_match_reference(pattern.as_bytes(), path.as_bytes()),
+                        "{pattern} on {path}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn path_match_hostile() {
+        // This would take a very long time if matching backtracked.
+        let pattern = format!("/{}b$", "*a".repeat(1000));
+        let path = format!("/{}", "a".repeat(10_000));
+        assert!(!Group::path_match(&pattern, &path));
+        assert!(Group::path_match(&pattern, &format!("{path}b")));
+    }
 
     #[test]
     fn parse_basic() {
@@ -260,5 +600,305 @@ allow: # no path
         p.assert_allowed(UsagePreferences::TRAIN_AI);
         let p = r.preferences("ExampleBot", "/allow/nope.jpg/blah").unwrap();
         p.assert_denied(UsagePreferences::SEARCH);
+
+        // Every line shares one vocabulary.
+        let other = r.preferences("whatever", "/allow").unwrap();
+        assert!(Arc::ptr_eq(p.vocabulary(), other.vocabulary()));
+        assert!(r.groups.iter().all(|g| {
+            g.usage_preferences
+                .iter()
+                .all(|u| Arc::ptr_eq(u.usage.vocabulary(), p.vocabulary()))
+        }));
+    }
+
+    /// The example from Section 5.1 of RFC 9309.
+    const RFC9309: &[u8] = b"User-Agent: *
+Disallow: *.gif$
+Disallow: /example/
+Allow: /publications/
+
+User-Agent: foobot
+Disallow:/
+Allow:/example/page.html
+Allow:/example/allowed.gif
+
+User-Agent: barbot
+User-Agent: bazbot
+Disallow: /example/page.html
+
+User-Agent: quxbot
+
+EOF
+";
+
+    #[test]
+    fn group_selection() {
+        let mut r = Robots::parse(RFC9309).unwrap();
+        assert_eq!(r.group_selection(), GroupSelection::Rfc9309);
+        assert!(r.preferences("foobot", "/example/page.html").is_some());
+        assert!(r.preferences("FooBot", "/example/allowed.gif").is_some());
+        assert!(r.preferences("foobot", "/publications/").is_none());
+        assert!(r.preferences("barbot", "/example/page.html").is_none());
+        assert!(r.preferences("bazbot", "/example/page.html").is_none());
+        assert!(r.preferences("otherbot", "/example/page.html").is_none());
+        assert!(r.preferences("otherbot", "/example/image.gif").is_none());
+        assert!(r.preferences("otherbot", "/publications/").is_some());
+
+        // Falling back allows the "*" group to permit what "foobot" does not.
+        r.set_group_selection(GroupSelection::Fallback);
+        assert!(r.preferences("foobot", "/publications/").is_some());
+        assert!(r.preferences("foobot", "/example/page.html").is_some());
+        assert!(r.preferences("barbot", "/example/page.html").is_none());
+    }
+
+    #[test]
+    fn default_allow() {
+        // An empty file allows everything.
+        let r = Robots::parse(&b""[..]).unwrap();
+        assert!(r.preferences("bot", "/").is_some());
+
+        // As does a group with only preferences.
+        let r = Robots::parse(&b"User-Agent: *\nContent-Usage: train-ai=n\n"[..]).unwrap();
+        let p = r.preferences("bot", "/anything").unwrap();
+        p.assert_denied(UsagePreferences::TRAIN_AI);
+
+        // An empty Disallow does nothing.
+        let r = Robots::parse(&b"User-Agent: *\nDisallow:\nDisallow: /x\n"[..]).unwrap();
+        assert!(r.preferences("bot", "/").is_some());
+        assert!(r.preferences("bot", "/x").is_none());
+
+        // The robots.txt file itself is always allowed.
+        let r = Robots::parse(&b"User-Agent: *\nDisallow: /\n"[..]).unwrap();
+        assert!(r.preferences("bot", "/robots.txt").is_some());
+        assert!(r.preferences("bot", "/robots.txt.bak").is_none());
+
+        // Paths that no rule matches in the RFC 9309 example.
+        let r = Robots::parse(RFC9309).unwrap();
+        assert!(r.preferences("barbot", "/example/").is_some());
+        assert!(r.preferences("barbot", "/publications/").is_some());
+        assert!(r.preferences("quxbot", "/example/").is_some());
+        assert!(r.preferences("quxbot", "/image.gif").is_some());
+        assert!(r.preferences("otherbot", "/").is_some());
+    }
+
+    #[test]
+    fn normalize_path() {
+        for (input, expected) in [
+            ("/", "/"),
+            ("/caf\u{e9}", "/caf%C3%A9"),
+            ("/caf%c3%a9", "/caf%C3%A9"),
+            ("/%7Efoo%2dbar", "/~foo-bar"),
+            ("/a%2Fb", "/a%2Fb"),
+            ("/a%2ab*", "/a%2Ab*"),
+            ("/%24$", "/%24$"),
+            ("/%", "/%"),
+            ("/%zz%4", "/%zz%4"),
+            ("/\u{1f600}", "/%F0%9F%98%80"),
+        ] {
+            assert_eq!(super::normalize_path(input), expected, "{input}");
+        }
+    }
+
+    #[test]
+    fn percent_encoding() {
+        const FILE: &[u8] = b"User-Agent: *
+Disallow: /caf%C3%A9
+Allow: /caf%c3%a9/menu
+Disallow: /na\xc3\xafve
+Allow: /%7Euser/
+Disallow: /%7Euser/private
+Content-Usage: train-ai=y
+Content-Usage: /r%C3%A9sum%C3%A9 train-ai=n
+Content-Usage: /%7Euser search=n
+";
+        let r = Robots::parse(FILE).unwrap();
+        assert!(r.preferences("bot", "/caf\u{e9}").is_none());
+        assert!(r.preferences("bot", "/caf%c3%a9").is_none());
+        assert!(r.preferences("bot", "/caf\u{e9}/menu").is_some());
+        assert!(r.preferences("bot", "/na%C3%AFve").is_none());
+        assert!(r.preferences("bot", "/~user/private").is_none());
+        assert!(r.preferences("bot", "/%7euser/private").is_none());
+
+        let p = r.preferences("bot", "/r\u{e9}sum\u{e9}.pdf").unwrap();
+        p.assert_denied(UsagePreferences::TRAIN_AI);
+        let p = r.preferences("bot", "/r%c3%a9sum%c3%a9").unwrap();
+        p.assert_denied(UsagePreferences::TRAIN_AI);
+        let p = r.preferences("bot", "/~user/index.html").unwrap();
+        p.assert_unset(UsagePreferences::TRAIN_AI);
+        p.assert_denied(UsagePreferences::SEARCH);
+        let p = r.preferences("bot", "/resume").unwrap();
+        p.assert_allowed(UsagePreferences::TRAIN_AI);
+    }
+
+    #[test]
+    fn line_endings() {
+        for file in [
+            &b"User-Agent: *\nDisallow: /\nAllow: /a\nContent-Usage: /a train-ai=n\n"[..],
+            b"User-Agent: *\rDisallow: /\rAllow: /a\rContent-Usage: /a train-ai=n\r",
+            b"User-Agent: *\r\nDisallow: /\r\nAllow: /a\r\nContent-Usage: /a train-ai=n",
+            b"\xef\xbb\xbfUser-Agent: *\r\nDisallow: /\rAllow: /a\nContent-Usage: /a train-ai=n",
+        ] {
+            // Reading one byte at a time splits every CRLF.
+            for capacity in [1, 4096] {
+                let r = Robots::parse_from(BufReader::with_capacity(capacity, file), "").unwrap();
+                assert!(r.preferences("bot", "/").is_none());
+                let p = r.preferences("bot", "/a").unwrap();
+                p.assert_denied(UsagePreferences::TRAIN_AI);
+                let source = &p.provenance(UsagePreferences::TRAIN_AI)[0];
+                assert_eq!(source.source.kind, SourceKind::RobotsTxt { line: 4 });
+                assert!(r.invalid_utf8_lines().is_empty());
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_utf8() {
+        const FILE: &[u8] =
+            b"User-Agent: *\r\nDisallow: /\xff\r\nAllow: /\r\nContent-Usage: train-ai=n # \xc3\r\n";
+        let r = Robots::parse(BufReader::with_capacity(1, FILE)).unwrap();
+        assert_eq!(r.invalid_utf8_lines(), [2, 4]);
+        assert!(r.preferences("bot", "/\u{fffd}").is_none());
+        let p = r.preferences("bot", "/").unwrap();
+        p.assert_denied(UsagePreferences::TRAIN_AI);
+    }
+
+    /// Groups for the same user agent are combined, as in Section 2.2.1 of RFC 9309.
+    #[test]
+    fn combined_groups() {
+        const FILE: &[u8] = b"user-agent: ExampleBot
+disallow: /foo
+disallow: /bar
+content-usage: train-ai=n
+
+user-agent: ExampleBot
+disallow: /baz
+allow: /baz/qux
+content-usage: /baz search=n
+
+user-agent: *
+allow: /
+content-usage: all=y
+";
+        let r = Robots::parse(FILE).unwrap();
+        assert!(r.preferences("examplebot", "/foo").is_none());
+        assert!(r.preferences("examplebot", "/baz").is_none());
+        let p = r.preferences("examplebot", "/baz/qux").unwrap();
+        p.assert_unset(UsagePreferences::ALL);
+        p.assert_denied(UsagePreferences::SEARCH);
+        let p = r.preferences("otherbot", "/baz").unwrap();
+        p.assert_allowed(UsagePreferences::ALL);
+    }
+
+    #[test]
+    fn provenance() {
+        const FILE: &[u8] =
+            b"User-Agent: *\nAllow: /\nContent-Usage: all=y\nContent-Usage: /x train-ai=n\n";
+        let r = Robots::parse_from(FILE, "example.com").unwrap();
+        let p = r.preferences("ExampleBot", "/x").unwrap();
+        p.assert_denied(UsagePreferences::TRAIN_GENAI);
+        let source = &p.provenance(UsagePreferences::TRAIN_GENAI)[0];
+        assert_eq!(
+            source.source,
+            Source::new(SourceKind::RobotsTxt { line: 4 }, "example.com")
+        );
+        assert_eq!(source.value, Denied);
+        assert_eq!(source.span, 0..10);
+
+        // Provenance is only recorded when an identifier is provided.
+        let r = Robots::parse(FILE).unwrap();
+        let p = r.preferences("ExampleBot", "/x").unwrap();
+        assert!(p.provenance(UsagePreferences::TRAIN_GENAI).is_empty());
+        assert!(p.provenance.is_empty());
+    }
+
+    const LIMITED: &[u8] = b"User-Agent: *
+Allow: /
+Content-Usage: all=y,search=n
+Content-Usage: /long train-ai=n
+Disallow: /private
+";
+
+    fn limits(robots_size: usize, robots_line_len: usize) -> ParseLimits {
+        ParseLimits {
+            robots_size,
+            robots_line_len,
+            ..ParseLimits::NONE
+        }
+    }
+
+    #[test]
+    fn unlimited() {
+        let r = Robots::parse_with_limits(LIMITED, None, ParseLimits::RECOMMENDED).unwrap();
+        assert!(!r.is_truncated());
+        assert!(r.preferences("bot", "/private").is_none());
+        let p = r.preferences("bot", "/long").unwrap();
+        p.assert_denied(UsagePreferences::TRAIN_AI);
+    }
+
+    #[test]
+    fn line_len() {
+        let r = Robots::parse_with_limits(LIMITED, None, limits(usize::MAX, 30)).unwrap();
+        assert!(r.is_truncated());
+        assert!(r.preferences("bot", "/private").is_none());
+        let p = r.preferences("bot", "/long").unwrap();
+        p.assert_allowed(UsagePreferences::TRAIN_AI);
+        p.assert_denied(UsagePreferences::SEARCH);
+    }
+
+    /// The limit applies to the content of a line, whatever ends it.
+    #[test]
+    fn line_len_boundary() {
+        const LINE: &str = "Disallow: /abc";
+        for ending in ["\n", "\r", "\r\n"] {
+            let file = format!("User-Agent: *{ending}{LINE}{ending}");
+            for capacity in [1, 64] {
+                let input = BufReader::with_capacity(capacity, file.as_bytes());
+                let r =
+                    Robots::parse_with_limits(input, None, limits(usize::MAX, LINE.len())).unwrap();
+                assert!(!r.is_truncated(), "{ending:?}");
+                assert!(r.preferences("bot", "/abc").is_none(), "{ending:?}");
+
+                let input = BufReader::with_capacity(capacity, file.as_bytes());
+                let r = Robots::parse_with_limits(input, None, limits(usize::MAX, LINE.len() - 1))
+                    .unwrap();
+                assert!(r.is_truncated(), "{ending:?}");
+                assert!(r.preferences("bot", "/abc").is_some(), "{ending:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn file_size() {
+        let r = Robots::parse_with_limits(LIMITED, None, limits(LIMITED.len() - 1, usize::MAX))
+            .unwrap();
+        assert!(r.is_truncated());
+        let p = r.preferences("bot", "/private").unwrap();
+        p.assert_allowed(UsagePreferences::TRAIN_AI);
+        p.assert_denied(UsagePreferences::SEARCH);
+    }
+
+    #[test]
+    fn endless() {
+        let input = BufReader::new(std::io::repeat(b'a'));
+        let r = Robots::parse_with_limits(input, None, ParseLimits::RECOMMENDED).unwrap();
+        assert!(r.is_truncated());
+        assert!(r.preferences("bot", "/").is_some());
+
+        let input = BufReader::new(LIMITED.chain(std::io::repeat(b'\n')));
+        let r = Robots::parse_with_limits(input, None, limits(1000, usize::MAX)).unwrap();
+        assert!(r.is_truncated());
+        assert!(r.preferences("bot", "/private").is_none());
+    }
+
+    #[test]
+    fn expression_len() {
+        let l = ParseLimits {
+            expression_len: 5,
+            ..ParseLimits::NONE
+        };
+        let r = Robots::parse_with_limits(LIMITED, None, l).unwrap();
+        assert!(r.is_truncated());
+        let p = r.preferences("bot", "/").unwrap();
+        p.assert_allowed(UsagePreferences::SEARCH);
     }
 }
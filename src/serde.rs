@@ -0,0 +1,309 @@
+use alloc::{borrow::Cow, string::String, sync::Arc, vec::Vec};
+use core::fmt;
+
+use ::serde::{
+    Deserialize, Deserializer, Serialize, Serializer,
+    de::{Error as _, MapAccess, Visitor},
+    ser::{SerializeMap, SerializeStruct},
+};
+
+use crate::{Registration, State, UsagePreferences, Vocabulary};
+
+/// Labels are bytes, but they are almost always text.
+fn label_str(label: &[u8]) -> Cow<'_, str> {
+    String::from_utf8_lossy(label)
+}
+
+/// Serialize a label as a string.
+pub(crate) fn serialize_label<S: Serializer>(label: &[u8], s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_str(&label_str(label))
+}
+
+fn value_str(value: State) -> Option<&'static str> {
+    match value {
+        State::Unknown => None,
+        State::Yes => Some("y"),
+        State::No => Some("n"),
+    }
+}
+
+/// A record is a map from each label to "y", "n", or null.
+/// Every label in the vocabulary is included, in order,
+/// followed by any preserved labels that are not in the vocabulary.
+impl Serialize for UsagePreferences {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        let mut map = s.serialize_map(Some(self.values.len() + self.unknown.len()))?;
+        for (i, value) in self.values.iter().enumerate() {
+            map.serialize_entry(&label_str(self.name(i)), &value_str(*value))?;
+        }
+        for (label, value) in &self.unknown {
+            map.serialize_entry(&label_str(label), &value_str(*value))?;
+        }
+        map.end()
+    }
+}
+
+struct RecordVisitor(UsagePreferences);
+
+impl<'de> Visitor<'de> for RecordVisitor {
+    type Value = UsagePreferences;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a map from label to \"y\", \"n\", or null")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(mut self, mut map: A) -> Result<Self::Value, A::Error> {
+        while let Some((label, value)) = map.next_entry::<String, Option<String>>()? {
+            let value = match value.as_deref() {
+                None => State::Unknown,
+                Some("y") => State::Yes,
+                Some("n") => State::No,
+                Some(v) => {
+                    return Err(A::Error::custom(format_args!(
+                        "invalid value \"{v}\" for \"{label}\""
+                    )));
+                }
+            };
+            if let Some(i) = self.0.index_of(label.as_bytes()) {
+                self.0.values[i] = value;
+            } else if self.0.preserve_unknown {
+                self.0.add_unknown(label.as_bytes(), value);
+            } else {
+                return Err(A::Error::custom(format_args!("unknown label \"{label}\"")));
+            }
+        }
+        Ok(self.0)
+    }
+}
+
+impl UsagePreferences {
+    /// Deserialize a record, as produced by the `Serialize` implementation,
+    /// for the labels in the given vocabulary.
+    ///
+    /// Labels that are not in the map have no value.
+    ///
+    /// # Errors
+    /// If the map includes a label that is not in the vocabulary or a value that is not
+    /// "y", "n", or null.
+    pub fn deserialize_with<'de, D: Deserializer<'de>>(
+        vocabulary: Arc<Vocabulary>,
+        d: D,
+    ) -> Result<Self, D::Error> {
+        d.deserialize_map(RecordVisitor(Self::new(vocabulary)))
+    }
+
+    /// Deserialize a record, as with `deserialize_with()`,
+    /// except that labels that are not in the vocabulary are preserved,
+    /// as though `set_preserve_unknown()` had been used.
+    ///
+    /// # Errors
+    /// If the map includes a value that is not "y", "n", or null.
+    pub fn deserialize_preserving_unknown<'de, D: Deserializer<'de>>(
+        vocabulary: Arc<Vocabulary>,
+        d: D,
+    ) -> Result<Self, D::Error> {
+        let mut prefs = Self::new(vocabulary);
+        prefs.set_preserve_unknown(true);
+        d.deserialize_map(RecordVisitor(prefs))
+    }
+}
+
+/// A label and the labels that it narrows, as serialized.
+#[derive(Serialize, Deserialize)]
+struct LabelEntry<'a> {
+    #[serde(borrow)]
+    label: Cow<'a, str>,
+    #[serde(borrow)]
+    narrows: Vec<Cow<'a, str>>,
+}
+
+/// A vocabulary is a list of labels, in order, each with the labels that it narrows,
+/// followed by any registrations.
+impl Serialize for Vocabulary {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        let labels = self
+            .labels
+            .iter()
+            .map(|l| LabelEntry {
+                label: label_str(&l.name),
+                narrows: l
+                    .parents
+                    .iter()
+                    .map(|&p| label_str(&self.labels[p].name))
+                    .collect(),
+            })
+            .collect::<Vec<_>>();
+        let mut st = s.serialize_struct("Vocabulary", 2)?;
+        st.serialize_field("labels", &labels)?;
+        st.serialize_field("registrations", self.registrations())?;
+        st.end()
+    }
+}
+
+#[derive(Deserialize)]
+struct VocabularyEntries<'a> {
+    #[serde(borrow)]
+    labels: Vec<LabelEntry<'a>>,
+    #[serde(default)]
+    registrations: Vec<Registration>,
+}
+
+impl<'de> Deserialize<'de> for Vocabulary {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let entries = VocabularyEntries::deserialize(d)?;
+        let mut v = Self::default();
+        for l in entries.labels {
+            let narrows = l.narrows.iter().map(|n| n.as_bytes());
+            v.try_add_with_parents(l.label.as_bytes(), narrows, &[])
+                .map_err(|e| D::Error::custom(format_args!("\"{}\": {e}", l.label)))?;
+        }
+        v.registrations = entries.registrations;
+        Ok(v)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use alloc::{string::ToString, sync::Arc};
+
+    use serde_json::json;
+
+    use crate::{UsagePreference, UsagePreferences, UsagePreferencesAssertions, Vocabulary};
+
+    #[test]
+    fn record() {
+        let mut up = UsagePreferences::default();
+        up.parse("all=y,train-ai=n");
+        let value = serde_json::to_value(&up).unwrap();
+        assert_eq!(
+            value,
+            json!({
+                "all": "y",
+                "train-ai": "n",
+                "train-genai": null,
+                "ai-use": null,
+                "search": null,
+            })
+        );
+
+        let v = Arc::clone(up.vocabulary());
+        let copy = UsagePreferences::deserialize_with(v, &value).unwrap();
+        assert_eq!(copy.to_string(), up.to_string());
+        copy.assert_denied(UsagePreferences::TRAIN_GENAI);
+
+        let v = Arc::clone(up.vocabulary());
+        let partial = UsagePreferences::deserialize_with(v, json!({ "search": "n" })).unwrap();
+        partial.assert_denied(UsagePreferences::SEARCH);
+        partial.assert_unset(UsagePreferences::ALL);
+
+        for bad in [
+            json!({ "tdm": "y" }),
+            json!({ "all": "yes" }),
+            json!(["all"]),
+        ] {
+            let v = Arc::clone(up.vocabulary());
+            assert!(UsagePreferences::deserialize_with(v, bad).is_err());
+        }
+    }
+
+    #[test]
+    fn record_unknown() {
+        let mut up = UsagePreferences::default();
+        up.set_preserve_unknown(true);
+        up.parse("tdm=n,search=y");
+        assert_eq!(
+            serde_json::to_value(&up).unwrap(),
+            json!({
+                "all": null,
+                "train-ai": null,
+                "train-genai": null,
+                "ai-use": null,
+                "search": "y",
+                "tdm": "n",
+            })
+        );
+
+        let value = serde_json::to_value(&up).unwrap();
+        let v = Arc::clone(up.vocabulary());
+        assert!(UsagePreferences::deserialize_with(Arc::clone(&v), &value).is_err());
+        let copy = UsagePreferences::deserialize_preserving_unknown(v, &value).unwrap();
+        assert!(copy.preserves_unknown());
+        assert_eq!(copy.to_string(), up.to_string());
+        assert_eq!(serde_json::to_value(&copy).unwrap(), value);
+    }
+
+    #[test]
+    fn vocabulary() {
+        let v = Vocabulary::draft();
+        let value = serde_json::to_value(&v).unwrap();
+        assert_eq!(
+            value["labels"],
+            json!([
+                { "label": "tdm", "narrows": [] },
+                { "label": "ai", "narrows": ["tdm"] },
+                { "label": "genai", "narrows": ["ai"] },
+                { "label": "search", "narrows": ["tdm"] },
+            ])
+        );
+        assert_eq!(value["registrations"][2]["label"], "genai");
+        assert_eq!(value["registrations"][2]["status"], "permanent");
+
+        let copy: Vocabulary = serde_json::from_value(value).unwrap();
+        assert_eq!(copy.registrations(), v.registrations());
+        let mut up = Arc::new(copy).preferences();
+        up.parse("ai=n");
+        up.assert_denied("genai");
+
+        let bad = json!({ "labels": [{ "label": "a", "narrows": ["b"] }] });
+        assert!(serde_json::from_value::<Vocabulary>(bad).is_err());
+    }
+
+    #[test]
+    fn usage_preference() {
+        assert_eq!(
+            serde_json::to_value(UsagePreference::Allowed).unwrap(),
+            json!("allowed")
+        );
+        assert_eq!(
+            serde_json::from_value::<UsagePreference>(json!("denied")).unwrap(),
+            UsagePreference::Denied
+        );
+    }
+
+    #[test]
+    fn explanation() {
+        let mut up = UsagePreferences::default();
+        up.parse("all=n");
+        let e = up.eval_explained(UsagePreferences::SEARCH, UsagePreference::Allowed);
+        assert_eq!(
+            serde_json::to_value(e).unwrap(),
+            json!({
+                "outcome": "denied",
+                "source": { "ancestor": "all" },
+                "trace": [
+                    { "label": "search", "value": null },
+                    { "label": "all", "value": "denied" },
+                ],
+            })
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "robots")]
+    fn robots() {
+        use crate::robots::Robots;
+
+        const FILE: &[u8] = b"user-agent: *\nallow: /\ncontent-usage: /a train-ai=n\n";
+        let r = Robots::parse(FILE).unwrap();
+        let value = serde_json::to_value(&r).unwrap();
+        let group = &value["groups"][0];
+        assert_eq!(group["user_agents"], json!(["*"]));
+        assert_eq!(group["admissions"][0]["allow"], true);
+        assert_eq!(group["usage_preferences"][0]["path"], "/a");
+        assert_eq!(
+            group["usage_preferences"][0]["usage"]["train-ai"],
+            json!("n")
+        );
+        assert_eq!(value["truncated"], false);
+    }
+}
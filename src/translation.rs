@@ -0,0 +1,200 @@
+use alloc::{sync::Arc, vec::Vec};
+
+use crate::{State, UsagePreferences, Vocabulary, VocabularyError};
+
+/// A mapping from the labels of one vocabulary to the labels of another.
+///
+/// A label can map to any number of labels in the other vocabulary.
+/// Only values that are explicitly set are carried across,
+/// so a label that has no mapping still takes its value
+/// from a more general label in the target vocabulary.
+/// For that to work, a label should only be mapped to labels
+/// that are at least as specific as it is.
+#[derive(Debug, Clone)]
+pub struct Translation {
+    source: Arc<Vocabulary>,
+    rules: Vec<(usize, Vec<u8>)>,
+}
+
+impl Translation {
+    /// Create an empty translation from the given vocabulary.
+    #[must_use]
+    pub fn new(source: Arc<Vocabulary>) -> Self {
+        Self {
+            source,
+            rules: Vec::new(),
+        }
+    }
+
+    /// Add a mapping from a label in the source vocabulary to a label in another.
+    ///
+    /// # Panics
+    /// This panics if the source label cannot be found.
+    pub fn add(&mut self, from: impl AsRef<[u8]>, to: impl AsRef<[u8]>) {
+        self.try_add(from, to)
+            .unwrap_or_else(|_| panic!("source label not found"));
+    }
+
+    /// Add a mapping, as with `add()`, but return an error rather than panicking.
+    ///
+    /// # Errors
+    /// If the source label cannot be found.
+    pub fn try_add(
+        &mut self,
+        from: impl AsRef<[u8]>,
+        to: impl AsRef<[u8]>,
+    ) -> Result<(), VocabularyError> {
+        let from = self
+            .source
+            .index_of(from)
+            .ok_or(VocabularyError::UnknownLabel)?;
+        self.rules.push((from, to.as_ref().to_vec()));
+        Ok(())
+    }
+
+    /// A translation from the labels in `Vocabulary::draft()`
+    /// to those in `Vocabulary::aipref()`.
+    ///
+    /// "ai" covers both training and use, so it maps to both "train-ai" and "ai-use".
+    #[must_use]
+    pub fn draft_to_aipref() -> Self {
+        let mut t = Self::new(Arc::new(Vocabulary::draft()));
+        t.add("tdm", UsagePreferences::ALL);
+        t.add("ai", UsagePreferences::TRAIN_AI);
+        t.add("ai", UsagePreferences::AI_USE);
+        t.add("genai", UsagePreferences::TRAIN_GENAI);
+        t.add("search", UsagePreferences::SEARCH);
+        t
+    }
+
+    /// A translation from the labels in `Vocabulary::aipref()`
+    /// to those in `Vocabulary::draft()`.
+    ///
+    /// The AI labels are each narrower than "ai" and "genai",
+    /// so they are not translated.
+    #[must_use]
+    pub fn aipref_to_draft() -> Self {
+        let mut t = Self::new(Arc::new(Vocabulary::aipref()));
+        t.add(UsagePreferences::ALL, "tdm");
+        t.add(UsagePreferences::SEARCH, "search");
+        t
+    }
+
+    /// Take the values from `from`, which uses the source vocabulary,
+    /// and add them to `to`.
+    /// Labels in `to` that are not in the target of any mapping are unaffected.
+    pub fn apply(&self, from: &UsagePreferences, to: &mut UsagePreferences) {
+        for (src, dst) in &self.rules {
+            let Some(src) = from.index_of(&self.source.labels[*src].name) else {
+                continue;
+            };
+            let Some(dst) = to.index_of(dst) else {
+                continue;
+            };
+            let value = from.values[src];
+            if value != State::Unknown {
+                to.values[dst].merge(value);
+            }
+        }
+    }
+
+    /// Parse an expression that uses the source vocabulary,
+    /// adding the translated values to `to`.
+    /// The expression is parsed with the limits from `to`,
+    /// and unknown labels are kept if `to` keeps them.
+    pub fn parse(&self, expr: impl AsRef<[u8]>, to: &mut UsagePreferences) {
+        let mut from = UsagePreferences::new(Arc::clone(&self.source));
+        from.set_limits(to.limits());
+        from.set_preserve_unknown(to.preserves_unknown());
+        from.parse(expr);
+        self.apply(&from, to);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use alloc::sync::Arc;
+
+    use super::Translation;
+    use crate::{
+        ParseLimits, UsagePreferences, UsagePreferencesAssertions, Vocabulary, VocabularyError,
+    };
+
+    #[test]
+    fn draft_to_aipref() {
+        let mut up = UsagePreferences::default();
+        Translation::draft_to_aipref().parse("tdm=y,ai=n", &mut up);
+        up.assert_allowed(UsagePreferences::ALL);
+        up.assert_denied(UsagePreferences::TRAIN_AI);
+        up.assert_denied(UsagePreferences::TRAIN_GENAI);
+        up.assert_denied(UsagePreferences::AI_USE);
+        up.assert_allowed(UsagePreferences::SEARCH);
+    }
+
+    #[test]
+    fn draft_to_aipref_specific() {
+        let mut up = UsagePreferences::default();
+        Translation::draft_to_aipref().parse("tdm=n,genai=y", &mut up);
+        up.assert_denied(UsagePreferences::ALL);
+        up.assert_denied(UsagePreferences::TRAIN_AI);
+        up.assert_allowed(UsagePreferences::TRAIN_GENAI);
+        up.assert_denied(UsagePreferences::AI_USE);
+        up.assert_denied(UsagePreferences::SEARCH);
+    }
+
+    #[test]
+    fn aipref_to_draft() {
+        let mut up = Arc::new(Vocabulary::draft()).preferences();
+        Translation::aipref_to_draft().parse("all=n,train-ai=y,search=y", &mut up);
+        up.assert_denied("tdm");
+        up.assert_denied("ai");
+        up.assert_denied("genai");
+        up.assert_allowed("search");
+    }
+
+    /// The limits that apply to `to` apply to the translated expression.
+    #[test]
+    fn limits() {
+        let mut up = UsagePreferences::default();
+        up.set_limits(ParseLimits {
+            expression_len: 5,
+            ..ParseLimits::NONE
+        });
+        Translation::draft_to_aipref().parse("tdm=y,ai=n", &mut up);
+        up.assert_allowed(UsagePreferences::ALL);
+        up.assert_allowed(UsagePreferences::TRAIN_AI);
+        up.assert_allowed(UsagePreferences::AI_USE);
+    }
+
+    /// Publishers use either vocabulary, so honour both.
+    #[test]
+    fn both() {
+        const EXPR: &str = "tdm=y,train-ai=n,ai=y";
+        let mut up = UsagePreferences::default();
+        up.parse(EXPR);
+        Translation::draft_to_aipref().parse(EXPR, &mut up);
+        up.assert_allowed(UsagePreferences::ALL);
+        up.assert_denied(UsagePreferences::TRAIN_AI);
+        up.assert_allowed(UsagePreferences::AI_USE);
+    }
+
+    #[test]
+    fn try_add() {
+        let mut t = Translation::new(Arc::new(Vocabulary::draft()));
+        assert_eq!(
+            t.try_add(UsagePreferences::ALL, UsagePreferences::ALL),
+            Err(VocabularyError::UnknownLabel)
+        );
+        assert_eq!(t.try_add("tdm", UsagePreferences::ALL), Ok(()));
+        let mut up = UsagePreferences::default();
+        t.parse("tdm=n", &mut up);
+        up.assert_denied(UsagePreferences::ALL);
+    }
+
+    #[test]
+    #[should_panic(expected = "source label not found")]
+    fn add_unknown() {
+        let mut t = Translation::new(Arc::new(Vocabulary::draft()));
+        t.add(UsagePreferences::ALL, UsagePreferences::ALL);
+    }
+}
@@ -0,0 +1,704 @@
+use alloc::{
+    string::{String, ToString},
+    sync::Arc,
+    vec,
+    vec::Vec,
+};
+use core::{cmp::max, error::Error, fmt, mem};
+
+use crate::{ExpressionFormat, UsagePreferences};
+
+/// The status of a label registration.
+#[derive(Default, PartialEq, Eq, Clone, Copy, Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum Status {
+    /// A provisional registration, which might omit some fields.
+    #[default]
+    Provisional,
+    /// A permanent registration.
+    Permanent,
+}
+
+/// A single entry from a label registry.
+///
+/// Provisional registrations can omit fields, which are left empty.
+#[derive(Default, PartialEq, Eq, Clone, Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
+pub struct Registration {
+    pub label: String,
+    pub definition: String,
+    /// The labels that this label is more specific than.
+    pub narrows: Vec<String>,
+    pub specification: String,
+    pub status: Status,
+    pub date: String,
+    pub change_controller: String,
+    pub contact: String,
+}
+
+/// The reasons that a registry might not be loaded.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum RegistryError {
+    /// The CSV could not be parsed; a quoted field was not closed.
+    Syntax { line: usize },
+    /// The header row did not include a "Label" column.
+    MissingLabelColumn,
+    /// A label was empty or contained a comma or equals sign.
+    InvalidLabel { line: usize },
+    /// The value of the "Status" field was not recognized.
+    InvalidStatus { line: usize },
+    /// The same label was registered more than once.
+    Duplicate(String),
+    /// A label narrows a label that is not registered.
+    UnknownNarrows { label: String, narrows: String },
+    /// The "Narrows" relationships form a cycle.
+    /// This is the label that closes the cycle,
+    /// and the other labels in it, in order, which are empty if the label narrows itself.
+    Cycle { label: String, through: Vec<String> },
+}
+
+impl fmt::Display for RegistryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Syntax { line } => write!(f, "unterminated quoted field on line {line}"),
+            Self::MissingLabelColumn => f.write_str("no \"Label\" column"),
+            Self::InvalidLabel { line } => write!(f, "invalid label on line {line}"),
+            Self::InvalidStatus { line } => write!(f, "invalid status on line {line}"),
+            Self::Duplicate(label) => write!(f, "duplicate label \"{label}\""),
+            Self::UnknownNarrows { label, narrows } => {
+                write!(f, "\"{label}\" narrows unknown label \"{narrows}\"")
+            }
+            Self::Cycle { label, through } if through.is_empty() => {
+                write!(f, "\"{label}\" narrows itself")
+            }
+            Self::Cycle { label, through } => {
+                write!(f, "\"{label}\" is part of a cycle through ")?;
+                for (i, other) in through.iter().enumerate() {
+                    let sep = if i == 0 { "" } else { ", " };
+                    write!(f, "{sep}\"{other}\"")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Error for RegistryError {}
+
+/// A protocol that carries preference expressions.
+///
+/// Each limits the characters that can be used in labels,
+/// as described in the "Label Characters" section of the draft.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Carrier {
+    /// robots.txt, where "#" starts a comment and a line ending ends the rule.
+    /// Whitespace separates a path from an expression,
+    /// so labels cannot contain whitespace or start with "/".
+    RobotsTxt,
+    /// An HTTP header field, where labels are Structured Field dictionary keys.
+    HttpHeader,
+}
+
+impl Carrier {
+    /// Find the first character in `label` that this carrier cannot convey.
+    fn find_unsupported(self, label: &[u8]) -> Option<usize> {
+        match self {
+            Self::RobotsTxt => label
+                .iter()
+                .position(|&c| c == b'#' || c.is_ascii_whitespace())
+                .or_else(|| label.starts_with(b"/").then_some(0)),
+            Self::HttpHeader => {
+                let first = label
+                    .first()
+                    .is_some_and(|&c| c.is_ascii_lowercase() || c == b'*');
+                if first {
+                    label.iter().position(|&c| {
+                        !(c.is_ascii_lowercase()
+                            || c.is_ascii_digit()
+                            || matches!(c, b'_' | b'-' | b'.' | b'*'))
+                    })
+                } else {
+                    Some(0)
+                }
+            }
+        }
+    }
+
+    /// The format of expressions in this carrier.
+    pub(crate) fn format(self) -> ExpressionFormat {
+        match self {
+            Self::RobotsTxt => ExpressionFormat::Loose,
+            Self::HttpHeader => ExpressionFormat::Dictionary,
+        }
+    }
+
+    /// Whether this carrier can convey `label`.
+    pub(crate) fn accepts(self, label: &[u8]) -> bool {
+        self.find_unsupported(label).is_none()
+    }
+}
+
+impl fmt::Display for Carrier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::RobotsTxt => "robots.txt",
+            Self::HttpHeader => "an HTTP header field",
+        })
+    }
+}
+
+/// The reasons that a label might not be added to a vocabulary.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum VocabularyError {
+    /// The label is empty.
+    Empty,
+    /// The label contains a comma.
+    Comma,
+    /// The label contains an equals sign.
+    Equals,
+    /// The label is already present.
+    Duplicate,
+    /// A parent label is not present.
+    UnknownParent,
+    /// A label that a mapping uses is not present.
+    UnknownLabel,
+    /// The label cannot be conveyed by a carrier,
+    /// because of the character at the given position.
+    UnsupportedCharacter { carrier: Carrier, position: usize },
+}
+
+impl fmt::Display for VocabularyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => f.write_str("usage name cannot be empty"),
+            Self::Comma => f.write_str("usage name cannot contain a comma"),
+            Self::Equals => f.write_str("usage name cannot contain equals"),
+            Self::Duplicate => f.write_str("duplicate usage added"),
+            Self::UnknownParent => f.write_str("parent not found"),
+            Self::UnknownLabel => f.write_str("usage not found"),
+            Self::UnsupportedCharacter { carrier, position } => {
+                write!(f, "character at {position} cannot be used in {carrier}")
+            }
+        }
+    }
+}
+
+impl Error for VocabularyError {}
+
+/// Split CSV input into records, per RFC 4180.
+/// Each record is returned with the line number on which it starts.
+/// This is lenient about line endings and about quotes that appear in unquoted fields.
+fn csv_records(input: &str) -> Result<Vec<(usize, Vec<String>)>, RegistryError> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut line = 1;
+    let mut start = line;
+    let mut chars = input.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if field.is_empty() => loop {
+                match chars.next() {
+                    Some('"') if chars.peek() == Some(&'"') => {
+                        field.push('"');
+                        chars.next();
+                    }
+                    Some('"') => break,
+                    Some(c) => {
+                        line += usize::from(c == '\n');
+                        field.push(c);
+                    }
+                    None => return Err(RegistryError::Syntax { line: start }),
+                }
+            },
+            ',' => record.push(mem::take(&mut field)),
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\n' => {
+                record.push(mem::take(&mut field));
+                if record.iter().any(|f| !f.is_empty()) {
+                    records.push((start, mem::take(&mut record)));
+                }
+                record.clear();
+                line += 1;
+                start = line;
+            }
+            c => field.push(c),
+        }
+    }
+    record.push(field);
+    if record.iter().any(|f| !f.is_empty()) {
+        records.push((start, record));
+    }
+    Ok(records)
+}
+
+/// The columns of the registry, in the order that they appear in `Registration`.
+const COLUMNS: &[&str] = &[
+    "label",
+    "definition",
+    "narrows",
+    "specification",
+    "status",
+    "date",
+    "change controller",
+    "contact",
+];
+
+impl Registration {
+    fn from_record(line: usize, fields: &[Option<&str>]) -> Result<Self, RegistryError> {
+        let field = |i: usize| fields[i].unwrap_or_default().trim().to_string();
+        let label = field(0);
+        if label.is_empty() || label.contains([',', '=']) {
+            return Err(RegistryError::InvalidLabel { line });
+        }
+        let status = match field(4).to_ascii_lowercase().as_str() {
+            "" | "provisional" => Status::Provisional,
+            "permanent" => Status::Permanent,
+            _ => return Err(RegistryError::InvalidStatus { line }),
+        };
+        Ok(Self {
+            label,
+            definition: field(1),
+            narrows: field(2)
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|n| !n.is_empty())
+                .map(String::from)
+                .collect(),
+            specification: field(3),
+            status,
+            date: field(5),
+            change_controller: field(6),
+            contact: field(7),
+        })
+    }
+}
+
+/// The labels defined in draft-thomson-aipref-sup.
+const DRAFT_REGISTRY: &str = "\
+Label,Definition,Narrows,Specification,Status,Change Controller,Contact
+tdm,Any automated process that extracts information from content,,draft-thomson-aipref-sup,permanent,IETF,IETF AI-PREF WG (ai-control@ietf.org)
+ai,Training or use of any machine learning system (or AI),tdm,draft-thomson-aipref-sup,permanent,IETF,IETF AI-PREF WG (ai-control@ietf.org)
+genai,Training or use of machine learning (or AI) that can generate content,ai,draft-thomson-aipref-sup,permanent,IETF,IETF AI-PREF WG (ai-control@ietf.org)
+search,Generation of search index or use for search applications,tdm,draft-thomson-aipref-sup,permanent,IETF,IETF AI-PREF WG (ai-control@ietf.org)
+";
+
+/// The labels defined in the AIPREF vocabulary, draft-ietf-aipref-vocab.
+/// These match the labels in `UsagePreferences::default()`.
+const AIPREF_REGISTRY: &str = "\
+Label,Definition,Narrows,Specification,Status,Change Controller,Contact
+all,Automated processing of assets to analyze text and data in order to generate information,,draft-ietf-aipref-vocab,permanent,IETF,IETF AI-PREF WG (ai-control@ietf.org)
+train-ai,Training machine learning models or artificial intelligence (AI),all,draft-ietf-aipref-vocab,permanent,IETF,IETF AI-PREF WG (ai-control@ietf.org)
+train-genai,\"Training AI models that generate text, images or other forms of synthetic content\",train-ai,draft-ietf-aipref-vocab,permanent,IETF,IETF AI-PREF WG (ai-control@ietf.org)
+ai-use,Using assets as input to a trained AI/ML model as part of the operation of that model,all,draft-ietf-aipref-vocab,permanent,IETF,IETF AI-PREF WG (ai-control@ietf.org)
+search,Using assets in a search application that directs users to the location of those assets,all,draft-ietf-aipref-vocab,permanent,IETF,IETF AI-PREF WG (ai-control@ietf.org)
+";
+
+/// A label and the labels that it is more specific than.
+#[derive(Debug, Clone)]
+pub(crate) struct Label {
+    pub(crate) name: Vec<u8>,
+    pub(crate) parents: Vec<usize>,
+}
+
+/// A set of labels, their hierarchy, and the registration details for each.
+///
+/// This does not change once it is built,
+/// so any number of `UsagePreferences` can share one copy through an `Arc`.
+/// `Default` produces a vocabulary with no labels.
+#[derive(Debug, Clone, Default)]
+pub struct Vocabulary {
+    pub(crate) labels: Vec<Label>,
+    /// The length of the longest label.
+    pub(crate) max_len: usize,
+    pub(crate) registrations: Vec<Registration>,
+}
+
+impl Vocabulary {
+    /// Load a vocabulary from a registry table in CSV form,
+    /// as IANA publishes registries.
+    ///
+    /// The first row is a header that names the columns:
+    /// "Label", "Definition", "Narrows", "Specification", "Status",
+    /// "Date", "Change Controller", and "Contact".
+    /// Only the "Label" column is required; columns can appear in any order
+    /// and unrecognized columns are ignored.
+    /// The "Narrows" field lists labels, separated by commas or whitespace.
+    /// Labels can be listed in any order.
+    ///
+    /// # Errors
+    /// If the table cannot be parsed or does not describe a valid hierarchy.
+    pub fn from_registry(csv: &str) -> Result<Self, RegistryError> {
+        let mut records = csv_records(csv)?.into_iter();
+        let Some((_, header)) = records.next() else {
+            return Err(RegistryError::MissingLabelColumn);
+        };
+        let columns = COLUMNS
+            .iter()
+            .map(|c| header.iter().position(|h| h.trim().eq_ignore_ascii_case(c)))
+            .collect::<Vec<_>>();
+        if columns[0].is_none() {
+            return Err(RegistryError::MissingLabelColumn);
+        }
+
+        let mut registrations = Vec::new();
+        for (line, record) in records {
+            let fields = columns
+                .iter()
+                .map(|c| c.and_then(|c| record.get(c)).map(String::as_str))
+                .collect::<Vec<_>>();
+            let r = Registration::from_record(line, &fields)?;
+            if registrations
+                .iter()
+                .any(|x: &Registration| x.label == r.label)
+            {
+                return Err(RegistryError::Duplicate(r.label));
+            }
+            registrations.push(r);
+        }
+        for r in &registrations {
+            if let Some(n) = r
+                .narrows
+                .iter()
+                .find(|&n| !registrations.iter().any(|x| &x.label == n))
+            {
+                return Err(RegistryError::UnknownNarrows {
+                    label: r.label.clone(),
+                    narrows: n.clone(),
+                });
+            }
+        }
+
+        // Add labels once everything they narrow has been added.
+        let mut v = Self::default();
+        let mut added = vec![false; registrations.len()];
+        while let Some(i) = (0..registrations.len()).find(|&i| {
+            !added[i]
+                && registrations[i]
+                    .narrows
+                    .iter()
+                    .all(|n| v.index_of(n.as_bytes()).is_some())
+        }) {
+            v.add_with_parents(&registrations[i].label, &registrations[i].narrows);
+            added[i] = true;
+        }
+        if let Some(start) = added.iter().position(|a| !a) {
+            // A label that was not added narrows another label that was not added,
+            // so following those from any of them leads around a cycle.
+            let mut path = vec![start];
+            loop {
+                let narrows = &registrations[path[path.len() - 1]].narrows;
+                let next = narrows
+                    .iter()
+                    .filter_map(|n| registrations.iter().position(|x| &x.label == n))
+                    .find(|&j| !added[j])
+                    .expect("a label that was not added narrows another");
+                if let Some(j) = path.iter().position(|&p| p == next) {
+                    return Err(RegistryError::Cycle {
+                        label: registrations[next].label.clone(),
+                        through: path[j + 1..]
+                            .iter()
+                            .map(|&p| registrations[p].label.clone())
+                            .collect(),
+                    });
+                }
+                path.push(next);
+            }
+        }
+
+        v.registrations = registrations;
+        Ok(v)
+    }
+
+    /// Check that a label can be added with the identified parents,
+    /// returning the index of each parent.
+    pub(crate) fn check(
+        &self,
+        usage: &[u8],
+        parents: impl IntoIterator<Item = impl AsRef<[u8]>>,
+        carriers: &[Carrier],
+    ) -> Result<Vec<usize>, VocabularyError> {
+        let parents = parents
+            .into_iter()
+            .map(|parent| self.index_of(parent).ok_or(VocabularyError::UnknownParent))
+            .collect::<Result<_, _>>()?;
+        if usage.is_empty() {
+            return Err(VocabularyError::Empty);
+        }
+        if usage.contains(&b',') {
+            return Err(VocabularyError::Comma);
+        }
+        if usage.contains(&b'=') {
+            return Err(VocabularyError::Equals);
+        }
+        for &carrier in carriers {
+            if let Some(position) = carrier.find_unsupported(usage) {
+                return Err(VocabularyError::UnsupportedCharacter { carrier, position });
+            }
+        }
+        if self.index_of(usage).is_some() {
+            return Err(VocabularyError::Duplicate);
+        }
+        Ok(parents)
+    }
+
+    /// Add a label that has passed `check()`.
+    pub(crate) fn insert(&mut self, usage: &[u8], parents: Vec<usize>) {
+        self.max_len = max(self.max_len, usage.len());
+        self.labels.push(Label {
+            name: usage.to_vec(),
+            parents,
+        });
+    }
+
+    /// Add a label, which has no registration.
+    ///
+    /// # Panics
+    /// This panics if the label is empty, already present, or contains a comma or equals sign.
+    pub fn add(&mut self, usage: impl AsRef<[u8]>) {
+        self.try_add(usage, &[]).unwrap_or_else(|e| panic!("{e}"));
+    }
+
+    /// Add a label that is more specific than the identified parent.
+    ///
+    /// # Panics
+    /// This panics if the label cannot be added or the parent cannot be found.
+    pub fn add_child(&mut self, usage: impl AsRef<[u8]>, parent: impl AsRef<[u8]>) {
+        self.add_with_parents(usage, [parent]);
+    }
+
+    /// Add a label that is more specific than all of the identified parents.
+    ///
+    /// # Panics
+    /// This panics if the label cannot be added or any parent cannot be found.
+    pub fn add_with_parents(
+        &mut self,
+        usage: impl AsRef<[u8]>,
+        parents: impl IntoIterator<Item = impl AsRef<[u8]>>,
+    ) {
+        self.try_add_with_parents(usage, parents, &[])
+            .unwrap_or_else(|e| panic!("{e}"));
+    }
+
+    /// Add a label, as with `add()`, but return an error rather than panicking.
+    /// The label must also be usable with each of the identified carriers.
+    ///
+    /// # Errors
+    /// If the label is empty, already present, or contains a character that cannot be used.
+    pub fn try_add(
+        &mut self,
+        usage: impl AsRef<[u8]>,
+        carriers: &[Carrier],
+    ) -> Result<(), VocabularyError> {
+        self.try_add_with_parents(usage, [] as [&[u8]; 0], carriers)
+    }
+
+    /// Add a label, as with `add_child()`, but return an error rather than panicking.
+    /// The label must also be usable with each of the identified carriers.
+    ///
+    /// # Errors
+    /// If the label cannot be added or the parent cannot be found.
+    pub fn try_add_child(
+        &mut self,
+        usage: impl AsRef<[u8]>,
+        parent: impl AsRef<[u8]>,
+        carriers: &[Carrier],
+    ) -> Result<(), VocabularyError> {
+        self.try_add_with_parents(usage, [parent], carriers)
+    }
+
+    /// Add a label, as with `add_with_parents()`, but return an error rather than panicking.
+    /// The label must also be usable with each of the identified carriers.
+    ///
+    /// # Errors
+    /// If the label cannot be added or any parent cannot be found.
+    pub fn try_add_with_parents(
+        &mut self,
+        usage: impl AsRef<[u8]>,
+        parents: impl IntoIterator<Item = impl AsRef<[u8]>>,
+        carriers: &[Carrier],
+    ) -> Result<(), VocabularyError> {
+        let usage = usage.as_ref();
+        let parents = self.check(usage, parents, carriers)?;
+        self.insert(usage, parents);
+        Ok(())
+    }
+
+    /// Find the index of the given label.
+    /// Labels are numbered from zero, in the order that they were added.
+    #[must_use]
+    pub fn index_of(&self, usage: impl AsRef<[u8]>) -> Option<usize> {
+        let usage = usage.as_ref();
+        self.labels.iter().position(|l| l.name == usage)
+    }
+
+    /// The number of labels.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.labels.len()
+    }
+
+    /// Whether there are no labels.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.labels.is_empty()
+    }
+
+    /// The vocabulary from draft-thomson-aipref-sup: "tdm", "ai", "genai", and "search".
+    #[must_use]
+    pub fn draft() -> Self {
+        Self::from_registry(DRAFT_REGISTRY).expect("draft registry is valid")
+    }
+
+    /// The vocabulary from the AIPREF working group:
+    /// "all", "train-ai", "train-genai", "ai-use", and "search".
+    /// This has the same labels as `UsagePreferences::default()`.
+    #[must_use]
+    pub fn aipref() -> Self {
+        Self::from_registry(AIPREF_REGISTRY).expect("AIPREF registry is valid")
+    }
+
+    /// Get the registration details for a label.
+    #[must_use]
+    pub fn registration(&self, label: impl AsRef<str>) -> Option<&Registration> {
+        let label = label.as_ref();
+        self.registrations.iter().find(|r| r.label == label)
+    }
+
+    /// Get the registration details for all labels, in the order that they were listed.
+    #[must_use]
+    pub fn registrations(&self) -> &[Registration] {
+        &self.registrations
+    }
+
+    /// Create a set of usage preferences that tracks the labels from this vocabulary,
+    /// none of which have a value.
+    ///
+    /// The vocabulary is shared, not copied.
+    #[must_use]
+    pub fn preferences(self: &Arc<Self>) -> UsagePreferences {
+        UsagePreferences::new(Arc::clone(self))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use alloc::{string::ToString, sync::Arc};
+
+    use super::{RegistryError, Status, Vocabulary};
+    use crate::{UsagePreferences, UsagePreferencesAssertions};
+
+    #[test]
+    fn draft_registry() {
+        let v = Arc::new(Vocabulary::draft());
+        assert_eq!(v.registrations().len(), 4);
+        let genai = v.registration("genai").unwrap();
+        assert_eq!(genai.narrows, ["ai"]);
+        assert_eq!(genai.status, Status::Permanent);
+        assert_eq!(genai.change_controller, "IETF");
+        assert!(v.registration("all").is_none());
+
+        let mut up = v.preferences();
+        up.parse("tdm=y,ai=n");
+        up.assert_allowed("tdm");
+        up.assert_denied("ai");
+        up.assert_denied("genai");
+        up.assert_allowed("search");
+    }
+
+    #[test]
+    fn aipref_registry() {
+        let v = Vocabulary::aipref();
+        let dflt = UsagePreferences::default();
+        let dflt = dflt.vocabulary();
+        assert_eq!(v.max_len, dflt.max_len);
+        for (a, b) in v.labels.iter().zip(&dflt.labels) {
+            assert_eq!(a.name, b.name);
+            assert_eq!(a.parents, b.parents);
+        }
+        assert_eq!(v.len(), dflt.len());
+    }
+
+    #[test]
+    fn provisional_out_of_order() {
+        let v = Vocabulary::from_registry(
+            "Narrows,Label,Other\n\
+             \"a b, c\",d,whatever\n\
+             ,a\n\
+             a,b\n\
+             \n\
+             ,c\n",
+        )
+        .unwrap();
+        let d = v.registration("d").unwrap();
+        assert_eq!(d.narrows, ["a", "b", "c"]);
+        assert_eq!(d.status, Status::Provisional);
+        assert_eq!(d.definition, "");
+
+        let mut up = Arc::new(v).preferences();
+        up.parse("a=y,c=n");
+        up.assert_allowed("b");
+        up.assert_denied("d");
+    }
+
+    #[test]
+    fn quoted_newline() {
+        let v = Vocabulary::from_registry("label,definition\nx,\"one\ntwo \"\"2\"\"\"\n").unwrap();
+        assert_eq!(v.registration("x").unwrap().definition, "one\ntwo \"2\"");
+    }
+
+    #[test]
+    fn errors() {
+        for (csv, err) in [
+            ("", RegistryError::MissingLabelColumn),
+            ("name\nx", RegistryError::MissingLabelColumn),
+            ("label\n\"x", RegistryError::Syntax { line: 2 }),
+            ("label\nx\n\"y,z\"", RegistryError::InvalidLabel { line: 3 }),
+            (
+                "label,narrows\nx,\n,x",
+                RegistryError::InvalidLabel { line: 3 },
+            ),
+            (
+                "label,status\nx,final",
+                RegistryError::InvalidStatus { line: 2 },
+            ),
+            ("label\nx\nx", RegistryError::Duplicate("x".into())),
+            (
+                "label,narrows\nx,y",
+                RegistryError::UnknownNarrows {
+                    label: "x".into(),
+                    narrows: "y".into(),
+                },
+            ),
+            ("label,narrows\nx,y\ny,x", cycle("x", &["y"])),
+            ("label,narrows\nx,x", cycle("x", &[])),
+            // "z" is not part of the cycle.
+            ("label,narrows\nz,x\nx,y\ny,\"w x\"\nw", cycle("x", &["y"])),
+        ] {
+            assert_eq!(Vocabulary::from_registry(csv).unwrap_err(), err, "{csv:?}");
+        }
+    }
+
+    fn cycle(label: &str, through: &[&str]) -> RegistryError {
+        RegistryError::Cycle {
+            label: label.into(),
+            through: through.iter().map(|&t| t.into()).collect(),
+        }
+    }
+
+    #[test]
+    fn cycle_display() {
+        assert_eq!(cycle("x", &[]).to_string(), "\"x\" narrows itself");
+        assert_eq!(
+            cycle("x", &["y", "z"]).to_string(),
+            "\"x\" is part of a cycle through \"y\", \"z\""
+        );
+    }
+}